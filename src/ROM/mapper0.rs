@@ -1,30 +1,32 @@
-use super::{Mapper, ROM};
+use serde::{Deserialize, Serialize};
 
-#[allow(dead_code)]
+use super::{CartridgeMemory, Mapper};
+
+// NROM: 16KB or 32KB PRG, 8KB CHR, no bank switching
+#[derive(Serialize, Deserialize)]
 pub struct Mapper0 {}
 
 impl Mapper for Mapper0 {
-    fn read(&self, rom: &ROM, address: &mut u16) -> u8 {
-        if *address < 0x2000 {
-            rom.chr
-                .as_ref()
-                .expect("chr is none, maybe you are using cpu to read chr")[*address as usize]
-        } else if *address >= 0x8000 {
-            let prg = rom
-                .prg
-                .as_ref()
-                .expect("prg is none, maybe you are using ppu to read prg");
-            let mut address = *address - 0x8000;
-            if prg.len() == 0x4000 && address >= 0x4000 {
-                address -= 0x4000;
-            }
-            prg[address as usize]
-        } else {
-            panic!("{:X?} address out of range!", address)
+    fn cpu_read(&mut self, mem: &CartridgeMemory, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF => mem.read_prg_ram(address),
+            // a 16KB PRG is mirrored at $C000
+            0x8000..=0xFFFF => Some(mem.prg_rom[(address - 0x8000) as usize % mem.prg_rom.len()]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, mem: &mut CartridgeMemory, address: u16, value: u8) {
+        if let 0x6000..=0x7FFF = address {
+            mem.write_prg_ram(address, value);
         }
     }
 
-    fn write(&mut self, _ram: &mut Vec<u8>, address: u16, _value: u8) {
-        panic!("{:X?} Attempt to write to Cartridge ROM space", address);
+    fn ppu_read(&mut self, mem: &CartridgeMemory, address: u16) -> u8 {
+        mem.chr[address as usize % mem.chr.len()]
+    }
+
+    fn ppu_write(&mut self, mem: &mut CartridgeMemory, address: u16, value: u8) {
+        mem.write_chr(address as usize, value);
     }
 }
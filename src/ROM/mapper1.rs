@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+
+use super::{CartridgeMemory, Mapper, Mirroring};
+
+// MMC1, registers are loaded one bit at a time through a 5-bit shift register
+//
+// $8000-$9FFF  control
+// $A000-$BFFF  CHR bank 0
+// $C000-$DFFF  CHR bank 1
+// $E000-$FFFF  PRG bank
+#[derive(Serialize, Deserialize)]
+pub struct Mapper1 {
+    shift: u8,
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_bank: u8,
+}
+
+// the shift register is full when the marker bit reaches bit 0
+const SHIFT_RESET: u8 = 0b1_0000;
+
+impl Mapper1 {
+    pub fn new() -> Self {
+        Mapper1 {
+            shift: SHIFT_RESET,
+            // power on in PRG mode 3, last bank fixed at $C000
+            control: 0b0_1100,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank0 = value,
+            0xC000..=0xDFFF => self.chr_bank1 = value,
+            _ => self.prg_bank = value,
+        }
+    }
+
+    // 4CPPM
+    // |||||
+    // |||++- Mirroring (0: one-screen, lower bank; 1: one-screen, upper bank;
+    // |||               2: vertical; 3: horizontal)
+    // |++--- PRG ROM bank mode (0, 1: switch 32 KB at $8000, ignoring low bit of bank number;
+    // |                         2: fix first bank at $8000 and switch 16 KB bank at $C000;
+    // |                         3: fix last bank at $C000 and switch 16 KB bank at $8000)
+    // +----- CHR ROM bank mode (0: switch 8 KB at a time; 1: switch two separate 4 KB banks)
+    fn prg_mode(&self) -> u8 {
+        (self.control >> 2) & 0b11
+    }
+
+    fn chr_4k_mode(&self) -> bool {
+        self.control & 0b1_0000 != 0
+    }
+
+    // R PPPP, PRG RAM is enabled while R is clear
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0b1_0000 == 0
+    }
+
+    fn prg_offset(&self, address: u16, prg_len: usize) -> usize {
+        let bank = (self.prg_bank & 0b1111) as usize;
+        let last = (prg_len / 0x4000).saturating_sub(1);
+        let offset = (address & 0x3FFF) as usize;
+        let bank = match (self.prg_mode(), address) {
+            (0 | 1, 0x8000..=0xBFFF) => bank & !1,
+            (0 | 1, _) => bank | 1,
+            (2, 0x8000..=0xBFFF) => 0,
+            (2, _) => bank,
+            (_, 0x8000..=0xBFFF) => bank,
+            (_, _) => last,
+        };
+        (bank * 0x4000 + offset) % prg_len
+    }
+
+    fn chr_offset(&self, address: u16, chr_len: usize) -> usize {
+        let offset = if self.chr_4k_mode() {
+            let bank = if address < 0x1000 {
+                self.chr_bank0
+            } else {
+                self.chr_bank1
+            };
+            bank as usize * 0x1000 + (address & 0x0FFF) as usize
+        } else {
+            (self.chr_bank0 & !1) as usize * 0x1000 + address as usize
+        };
+        offset % chr_len
+    }
+}
+
+impl Mapper for Mapper1 {
+    fn cpu_read(&mut self, mem: &CartridgeMemory, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => mem.read_prg_ram(address),
+            0x8000..=0xFFFF => Some(mem.prg_rom[self.prg_offset(address, mem.prg_rom.len())]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, mem: &mut CartridgeMemory, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => mem.write_prg_ram(address, value),
+            0x8000..=0xFFFF => {
+                if value & 0b1000_0000 != 0 {
+                    self.shift = SHIFT_RESET;
+                    self.control |= 0b0_1100;
+                    return;
+                }
+                let full = self.shift & 1 == 1;
+                self.shift = (self.shift >> 1) | ((value & 1) << 4);
+                if full {
+                    self.write_register(address, self.shift);
+                    self.shift = SHIFT_RESET;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, mem: &CartridgeMemory, address: u16) -> u8 {
+        mem.chr[self.chr_offset(address, mem.chr.len())]
+    }
+
+    fn ppu_write(&mut self, mem: &mut CartridgeMemory, address: u16, value: u8) {
+        mem.write_chr(self.chr_offset(address, mem.chr.len()), value);
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match self.control & 0b11 {
+            0 => Mirroring::SINGLE_SCREEN_LOWER_BANK,
+            1 => Mirroring::SINGLE_SCREEN_UPPER_BANK,
+            2 => Mirroring::VERTICAL,
+            _ => Mirroring::HORIZONTAL,
+        })
+    }
+}
+
+impl Default for Mapper1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+use super::{CartridgeMemory, Mapper};
+
+// UxROM: switchable 16KB PRG bank at $8000, last bank fixed at $C000
+#[derive(Serialize, Deserialize)]
+pub struct Mapper2 {
+    prg_bank: u8,
+    bus_conflicts: bool,
+}
+
+impl Mapper2 {
+    // NES 2.0 submapper 1 marks boards without bus conflicts
+    pub fn new(submapper: u8) -> Self {
+        Mapper2 {
+            prg_bank: 0,
+            bus_conflicts: submapper != 1,
+        }
+    }
+}
+
+impl Mapper for Mapper2 {
+    fn cpu_read(&mut self, mem: &CartridgeMemory, address: u16) -> Option<u8> {
+        if address < 0x8000 {
+            return None;
+        }
+        let prg = &mem.prg_rom;
+        let bank = if address < 0xC000 {
+            self.prg_bank as usize
+        } else {
+            (prg.len() / 0x4000).saturating_sub(1)
+        };
+        Some(prg[(bank * 0x4000 + (address & 0x3FFF) as usize) % prg.len()])
+    }
+
+    fn cpu_write(&mut self, _mem: &mut CartridgeMemory, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.prg_bank = value;
+        }
+    }
+
+    fn ppu_read(&mut self, mem: &CartridgeMemory, address: u16) -> u8 {
+        mem.chr[address as usize % mem.chr.len()]
+    }
+
+    fn ppu_write(&mut self, mem: &mut CartridgeMemory, address: u16, value: u8) {
+        mem.write_chr(address as usize, value);
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
+}
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+use super::{CartridgeMemory, Mapper};
+
+// CNROM: fixed PRG, switchable 8KB CHR bank
+#[derive(Serialize, Deserialize)]
+pub struct Mapper3 {
+    chr_bank: u8,
+    bus_conflicts: bool,
+}
+
+impl Mapper3 {
+    // NES 2.0 submapper 1 marks boards without bus conflicts
+    pub fn new(submapper: u8) -> Self {
+        Mapper3 {
+            chr_bank: 0,
+            bus_conflicts: submapper != 1,
+        }
+    }
+}
+
+impl Mapper for Mapper3 {
+    fn cpu_read(&mut self, mem: &CartridgeMemory, address: u16) -> Option<u8> {
+        if address < 0x8000 {
+            return None;
+        }
+        Some(mem.prg_rom[(address - 0x8000) as usize % mem.prg_rom.len()])
+    }
+
+    fn cpu_write(&mut self, _mem: &mut CartridgeMemory, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.chr_bank = value;
+        }
+    }
+
+    fn ppu_read(&mut self, mem: &CartridgeMemory, address: u16) -> u8 {
+        mem.chr[(self.chr_bank as usize * 0x2000 + address as usize) % mem.chr.len()]
+    }
+
+    fn ppu_write(&mut self, mem: &mut CartridgeMemory, address: u16, value: u8) {
+        mem.write_chr(self.chr_bank as usize * 0x2000 + address as usize, value);
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
+}
@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+
+use super::{CartridgeMemory, Mapper, Mirroring};
+
+// PPU dots A12 has to stay low before a rise clocks the IRQ counter, the
+// MMC3 filters out the short drops between sprite pattern fetches
+const A12_LOW_DOTS: u64 = 10;
+
+// MMC3
+//
+// $8000-$9FFE  bank select (even), bank data (odd)
+// $A000-$BFFF  mirroring (even), PRG RAM protect (odd)
+// $C000-$DFFE  IRQ latch (even), IRQ reload (odd)
+// $E000-$FFFF  IRQ disable (even), IRQ enable (odd)
+#[derive(Serialize, Deserialize)]
+pub struct Mapper4 {
+    bank_select: u8,
+    // R0 - R5 select CHR banks, R6 and R7 PRG banks
+    banks: [u8; 8],
+    horizontal_mirroring: bool,
+    four_screen: bool,
+    prg_ram_enabled: bool,
+    prg_ram_write_protect: bool,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    // PPU cycle A12 was last seen going low, None while it is high
+    a12_low_since: Option<u64>,
+}
+
+impl Mapper4 {
+    pub fn new(four_screen: bool) -> Self {
+        Mapper4 {
+            bank_select: 0,
+            banks: [0; 8],
+            horizontal_mirroring: false,
+            four_screen,
+            prg_ram_enabled: true,
+            prg_ram_write_protect: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12_low_since: Some(0),
+        }
+    }
+
+    // CPMx xRRR
+    // ||    |||
+    // ||    +++- Bank register to update on the next write to bank data
+    // |+-------- PRG ROM bank mode (0: $8000-$9FFF swappable, $C000-$DFFF fixed
+    // |                             to second-last bank; 1: the two swapped)
+    // +--------- CHR A12 inversion (0: two 2 KB banks at $0000-$0FFF, four 1 KB
+    //                               banks at $1000-$1FFF; 1: the reverse)
+    fn prg_swapped(&self) -> bool {
+        self.bank_select & 0b0100_0000 != 0
+    }
+
+    fn chr_inverted(&self) -> bool {
+        self.bank_select & 0b1000_0000 != 0
+    }
+
+    fn prg_offset(&self, address: u16, prg_len: usize) -> usize {
+        let second_last = (prg_len / 0x2000).saturating_sub(2);
+        let bank = match (address, self.prg_swapped()) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.banks[6] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => second_last,
+            (0xA000..=0xBFFF, _) => self.banks[7] as usize,
+            _ => second_last + 1,
+        };
+        (bank * 0x2000 + (address & 0x1FFF) as usize) % prg_len
+    }
+
+    fn chr_offset(&self, address: u16, chr_len: usize) -> usize {
+        let address = if self.chr_inverted() {
+            address ^ 0x1000
+        } else {
+            address
+        };
+        let bank = match address {
+            0x0000..=0x07FF => (self.banks[0] & 0xFE) as usize + (address >> 10) as usize % 2,
+            0x0800..=0x0FFF => (self.banks[1] & 0xFE) as usize + (address >> 10) as usize % 2,
+            _ => self.banks[2 + ((address - 0x1000) >> 10) as usize] as usize,
+        };
+        (bank * 0x0400 + (address & 0x03FF) as usize) % chr_len
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mapper4 {
+    fn cpu_read(&mut self, mem: &CartridgeMemory, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled => mem.read_prg_ram(address),
+            0x8000..=0xFFFF => Some(mem.prg_rom[self.prg_offset(address, mem.prg_rom.len())]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, mem: &mut CartridgeMemory, address: u16, value: u8) {
+        let even = address & 1 == 0;
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram_write_protect => {
+                mem.write_prg_ram(address, value)
+            }
+            0x8000..=0x9FFF if even => self.bank_select = value,
+            0x8000..=0x9FFF => self.banks[(self.bank_select & 0b111) as usize] = value,
+            0xA000..=0xBFFF if even => self.horizontal_mirroring = value & 1 == 1,
+            0xA000..=0xBFFF => {
+                self.prg_ram_enabled = value & 0b1000_0000 != 0;
+                self.prg_ram_write_protect = value & 0b0100_0000 != 0;
+            }
+            0xC000..=0xDFFF if even => self.irq_latch = value,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, mem: &CartridgeMemory, address: u16) -> u8 {
+        mem.chr[self.chr_offset(address, mem.chr.len())]
+    }
+
+    fn ppu_write(&mut self, mem: &mut CartridgeMemory, address: u16, value: u8) {
+        mem.write_chr(self.chr_offset(address, mem.chr.len()), value);
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        if self.four_screen {
+            None
+        } else if self.horizontal_mirroring {
+            Some(Mirroring::HORIZONTAL)
+        } else {
+            Some(Mirroring::VERTICAL)
+        }
+    }
+
+    // the IRQ counter is clocked by rising edges of PPU A12
+    fn ppu_address(&mut self, address: u16, cycle: u64) {
+        let a12 = address & 0x1000 != 0;
+        match (a12, self.a12_low_since) {
+            (true, Some(since)) => {
+                if cycle.saturating_sub(since) >= A12_LOW_DOTS {
+                    self.clock_irq_counter();
+                }
+                self.a12_low_since = None;
+            }
+            (false, None) => self.a12_low_since = Some(cycle),
+            _ => {}
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+}
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+
+use super::{CartridgeMemory, Mapper, Mirroring};
+
+// AxROM: switchable 32KB PRG bank and single-screen mirroring
+//
+// 7  bit  0
+// ---- ----
+// xxxM xPPP
+//    |  |||
+//    |  +++- Select 32 KB PRG ROM bank for CPU $8000-$FFFF
+//    +------ Select 1 KB VRAM page for all 4 nametables
+#[derive(Serialize, Deserialize)]
+pub struct Mapper7 {
+    bank: u8,
+    bus_conflicts: bool,
+}
+
+impl Mapper7 {
+    // only AMROM boards have bus conflicts, ANROM and AOROM do not.
+    // NES 2.0 submapper 2 marks them
+    pub fn new(submapper: u8) -> Self {
+        Mapper7 {
+            bank: 0,
+            bus_conflicts: submapper == 2,
+        }
+    }
+}
+
+impl Mapper for Mapper7 {
+    fn cpu_read(&mut self, mem: &CartridgeMemory, address: u16) -> Option<u8> {
+        if address < 0x8000 {
+            return None;
+        }
+        let bank = (self.bank & 0b111) as usize;
+        Some(mem.prg_rom[(bank * 0x8000 + (address & 0x7FFF) as usize) % mem.prg_rom.len()])
+    }
+
+    fn cpu_write(&mut self, _mem: &mut CartridgeMemory, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.bank = value;
+        }
+    }
+
+    fn ppu_read(&mut self, mem: &CartridgeMemory, address: u16) -> u8 {
+        mem.chr[address as usize % mem.chr.len()]
+    }
+
+    fn ppu_write(&mut self, mem: &mut CartridgeMemory, address: u16, value: u8) {
+        mem.write_chr(address as usize, value);
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(if self.bank & 0b1_0000 == 0 {
+            Mirroring::SINGLE_SCREEN_LOWER_BANK
+        } else {
+            Mirroring::SINGLE_SCREEN_UPPER_BANK
+        })
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
+}
@@ -1,113 +1,366 @@
+use std::fmt;
+
 use log::debug;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::consts::{CHR_ROM_PAGE_SIZE, NES_TAG, PRG_ROM_PAGE_SIZE};
+use crate::{
+    clock::Region,
+    consts::{CHR_ROM_PAGE_SIZE, NES_TAG, PRG_RAM_PAGE_SIZE, PRG_ROM_PAGE_SIZE},
+};
 
-use self::mapper0::Mapper0;
+use self::{
+    mapper0::Mapper0, mapper1::Mapper1, mapper2::Mapper2, mapper3::Mapper3, mapper4::Mapper4,
+    mapper7::Mapper7,
+};
 
 pub mod mapper0;
+pub mod mapper1;
+pub mod mapper2;
+pub mod mapper3;
+pub mod mapper4;
+pub mod mapper7;
 
-pub trait Mapper {
-    fn read(&self, rom: &ROM, address: &mut u16) -> u8;
-    fn write(&mut self, ram: &mut Vec<u8>, address: u16, value: u8);
+// the cartridge's memories, the mapper banks them into the CPU and PPU address spaces
+pub struct CartridgeMemory {
+    pub prg_rom: Vec<u8>,
+    // CHR-ROM, or CHR-RAM on carts without CHR-ROM banks
+    pub chr: Vec<u8>,
+    pub chr_ram: bool,
+    pub prg_ram: Vec<u8>,
 }
 
-// #[allow(non_camel_case_types)]
-// enum Mirror {
-//     HORIZONTAL,
-//     VERTICAL,
-//     FOUR_SCREEN,
-//     SINGLE_SCREEN_LOWER_BANK,
-//     SINGLE_SCREEN_UPPER_BANK,
-// }
+impl CartridgeMemory {
+    // $6000-$7FFF, mirrored when the RAM is smaller than 8KB
+    pub fn read_prg_ram(&self, address: u16) -> Option<u8> {
+        if self.prg_ram.is_empty() {
+            return None;
+        }
+        Some(self.prg_ram[(address - 0x6000) as usize % self.prg_ram.len()])
+    }
+
+    pub fn write_prg_ram(&mut self, address: u16, value: u8) {
+        if !self.prg_ram.is_empty() {
+            let len = self.prg_ram.len();
+            self.prg_ram[(address - 0x6000) as usize % len] = value;
+        }
+    }
+
+    // pattern table writes only land on CHR-RAM
+    pub fn write_chr(&mut self, offset: usize, value: u8) {
+        if self.chr_ram {
+            let len = self.chr.len();
+            self.chr[offset % len] = value;
+        }
+    }
+}
+
+pub trait Mapper: MapperState {
+    // $4020-$FFFF, None leaves the CPU data bus floating
+    fn cpu_read(&mut self, mem: &CartridgeMemory, address: u16) -> Option<u8>;
+    fn cpu_write(&mut self, mem: &mut CartridgeMemory, address: u16, value: u8);
+
+    // $0000-$1FFF, the pattern tables
+    fn ppu_read(&mut self, mem: &CartridgeMemory, address: u16) -> u8;
+    fn ppu_write(&mut self, _mem: &mut CartridgeMemory, _address: u16, _value: u8) {}
+
+    // mappers that switch mirroring at runtime override the header setting
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    // discrete logic boards whose ROM drives the data bus during register writes
+    fn bus_conflicts(&self) -> bool {
+        false
+    }
+
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // called once per CPU cycle
+    fn cpu_cycle(&mut self) {}
+
+    // every address the PPU puts on its bus, with the PPU cycle it happened on
+    fn ppu_address(&mut self, _address: u16, _cycle: u64) {}
+}
+
+// bank registers and other mapper state carried in save states
+pub trait MapperState {
+    fn save_state(&self) -> serde_json::Value;
+    fn load_state(&mut self, state: serde_json::Value) -> Result<(), serde_json::Error>;
+}
+
+impl<T: Serialize + DeserializeOwned> MapperState for T {
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("encode mapper state failed!")
+    }
+
+    // a state saved by another mapper fails to decode and leaves this one untouched
+    fn load_state(&mut self, state: serde_json::Value) -> Result<(), serde_json::Error> {
+        *self = serde_json::from_value(state)?;
+        Ok(())
+    }
+}
 
 #[allow(non_camel_case_types)]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Mirroring {
     VERTICAL,
     HORIZONTAL,
     FOUR_SCREEN,
+    SINGLE_SCREEN_LOWER_BANK,
+    SINGLE_SCREEN_UPPER_BANK,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RomError {
+    BadMagic,
+    TruncatedPrg { expected: usize, actual: usize },
+    TruncatedChr { expected: usize, actual: usize },
+    UnsupportedMapper(u16),
+    UnsupportedFormat(&'static str),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::BadMagic => write!(f, "not an iNES file"),
+            RomError::TruncatedPrg { expected, actual } => write!(
+                f,
+                "truncated PRG ROM: expected {} bytes, found {}",
+                expected, actual
+            ),
+            RomError::TruncatedChr { expected, actual } => write!(
+                f,
+                "truncated CHR ROM: expected {} bytes, found {}",
+                expected, actual
+            ),
+            RomError::UnsupportedMapper(mapper) => write!(f, "unsupported mapper {}", mapper),
+            RomError::UnsupportedFormat(reason) => write!(f, "unsupported format: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConsoleType {
+    NES,
+    VS_SYSTEM { ppu_type: u8, hardware_type: u8 },
+    PLAYCHOICE_10,
+    // NES 2.0 extended console type, byte 13
+    EXTENDED(u8),
 }
 
+#[derive(Debug, Clone)]
 pub struct Header {
-    prg_rom_start: usize,
-    prg_rom_size: usize,
-    chr_rom_start: usize,
-    chr_rom_size: usize,
-    ram_size: usize,
-    mapper: u8,
-    screen_mirroring: Mirroring,
+    pub nes2: bool,
+    pub prg_rom_start: usize,
+    pub prg_rom_size: usize,
+    pub chr_rom_start: usize,
+    pub chr_rom_size: usize,
+    // volatile and battery-backed RAM sizes in bytes
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub screen_mirroring: Mirroring,
+    pub battery: bool,
+    pub region: Region,
+    pub console_type: ConsoleType,
+    // number of miscellaneous ROMs stored after CHR
+    pub misc_roms: u8,
+    pub expansion_device: u8,
+}
+
+// the writable parts of a cartridge, saved alongside the console state
+#[derive(Serialize, Deserialize)]
+pub struct CartridgeState {
+    prg_ram: Vec<u8>,
+    #[serde(default)]
+    chr_ram: Vec<u8>,
+    mapper: serde_json::Value,
 }
 
+// a cartridge, shared by the CPU and the PPU buses
 pub struct ROM {
-    pub prg: Option<Vec<u8>>,
-    pub chr: Option<Vec<u8>>,
-    ram: Vec<u8>,
-    mapper: Box<dyn Mapper + Sync + Send + 'static>,
-    #[allow(dead_code)]
-    screen_mirroring: Mirroring,
+    mem: CartridgeMemory,
+    mapper: Box<dyn Mapper>,
+    header: Header,
 }
 
 impl ROM {
-    pub fn new(data: Vec<u8>, part: &str) -> Self {
-        let header = parse_header(&data);
-        let mapper = match header.mapper {
-            0 => Box::new(Mapper0 {}),
-            _ => panic!("Unknown mapper"),
+    pub fn new(data: Vec<u8>) -> Result<Self, RomError> {
+        let header = parse_header(&data)?;
+        // the mapper and the PRG bank size its banking assumes
+        let (mapper, prg_bank_size): (Box<dyn Mapper>, usize) = match header.mapper {
+            0 => (Box::new(Mapper0 {}), 0x4000),
+            1 => (Box::new(Mapper1::new()), 0x4000),
+            2 => (Box::new(Mapper2::new(header.submapper)), 0x4000),
+            3 => (Box::new(Mapper3::new(header.submapper)), 0x4000),
+            4 => (
+                Box::new(Mapper4::new(
+                    header.screen_mirroring == Mirroring::FOUR_SCREEN,
+                )),
+                0x2000,
+            ),
+            7 => (Box::new(Mapper7::new(header.submapper)), 0x8000),
+            mapper => return Err(RomError::UnsupportedMapper(mapper)),
         };
+        // NES 2.0 exponent-multiplier sizes can describe less than one bank
+        if header.prg_rom_size % prg_bank_size != 0 {
+            return Err(RomError::UnsupportedFormat(
+                "PRG ROM is not a whole number of banks",
+            ));
+        }
 
         debug!(
             "base rom size: {}; load rom size {}",
             data.len(),
-            header.chr_rom_start + header.chr_rom_size
+            header.chr_rom_start.saturating_add(header.chr_rom_size)
         );
 
-        let mut prg = None;
-        let mut chr = None;
-
-        match part {
-            "cpu" => {
-                prg = Some(
-                    data[header.prg_rom_start..(header.prg_rom_start + header.prg_rom_size)]
-                        .to_vec(),
-                )
-            }
-            "ppu" => {
-                chr = Some(
-                    data[header.chr_rom_start..(header.chr_rom_start + header.chr_rom_size)]
-                        .to_vec(),
-                )
-            }
-            _ => panic!("unknown part!"),
-        }
+        let prg_rom = slice(&data, header.prg_rom_start, header.prg_rom_size).ok_or(
+            RomError::TruncatedPrg {
+                expected: header.prg_rom_size,
+                actual: data.len().saturating_sub(header.prg_rom_start),
+            },
+        )?;
+        let chr_ram = header.chr_rom_size == 0;
+        let chr = if chr_ram {
+            // NES 2.0 headers may leave the CHR-RAM size out, the boards carry 8KB
+            let size = header.chr_ram_size + header.chr_nvram_size;
+            vec![0; if size == 0 { CHR_ROM_PAGE_SIZE } else { size }]
+        } else {
+            slice(&data, header.chr_rom_start, header.chr_rom_size).ok_or(
+                RomError::TruncatedChr {
+                    expected: header.chr_rom_size,
+                    actual: data.len().saturating_sub(header.chr_rom_start),
+                },
+            )?
+        };
 
-        ROM {
-            prg,
-            chr,
-            ram: vec![0; header.ram_size],
+        Ok(ROM {
+            mem: CartridgeMemory {
+                prg_rom,
+                chr,
+                chr_ram,
+                prg_ram: vec![0; header.prg_ram_size + header.prg_nvram_size],
+            },
             mapper,
-            screen_mirroring: header.screen_mirroring,
+            header,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper
+            .mirroring()
+            .unwrap_or(self.header.screen_mirroring)
+    }
+
+    pub fn region(&self) -> Region {
+        self.header.region
+    }
+
+    // the battery-backed PRG-RAM, the contents of a .sav file. It follows the
+    // volatile PRG-RAM, which is not saved
+    pub fn battery_ram(&self) -> Option<&[u8]> {
+        let nvram = &self.mem.prg_ram[self.header.prg_ram_size..];
+        if self.header.battery && !nvram.is_empty() {
+            Some(nvram)
+        } else {
+            None
         }
     }
 
-    pub fn read(&self, address: &mut u16) -> u8 {
-        self.mapper.read(self, address)
+    // a .sav of a different size fills what fits
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        if self.header.battery {
+            copy_fit(&mut self.mem.prg_ram[self.header.prg_ram_size..], data);
+        }
     }
 
-    pub fn write(&mut self, address: u16, value: u8) {
-        self.mapper.write(&mut self.ram, address, value);
+    pub fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        self.mapper.cpu_read(&self.mem, address)
+    }
+
+    pub fn cpu_write(&mut self, address: u16, value: u8) {
+        // on boards with bus conflicts the ROM output is ANDed with the CPU's
+        let value = if address >= 0x8000 && self.mapper.bus_conflicts() {
+            value & self.mapper.cpu_read(&self.mem, address).unwrap_or(0xFF)
+        } else {
+            value
+        };
+        self.mapper.cpu_write(&mut self.mem, address, value);
+    }
+
+    pub fn ppu_read(&mut self, address: u16) -> u8 {
+        self.mapper.ppu_read(&self.mem, address)
+    }
+
+    pub fn ppu_write(&mut self, address: u16, value: u8) {
+        self.mapper.ppu_write(&mut self.mem, address, value);
+    }
+
+    pub fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    pub fn cpu_cycle(&mut self) {
+        self.mapper.cpu_cycle();
+    }
+
+    pub fn ppu_address(&mut self, address: u16, cycle: u64) {
+        self.mapper.ppu_address(address, cycle);
+    }
+
+    pub fn save_state(&self) -> CartridgeState {
+        CartridgeState {
+            prg_ram: self.mem.prg_ram.clone(),
+            chr_ram: if self.mem.chr_ram {
+                self.mem.chr.clone()
+            } else {
+                Vec::new()
+            },
+            mapper: self.mapper.save_state(),
+        }
     }
-}
 
-fn parse_header(data: &[u8]) -> Header {
-    let header = &data[0..16];
-    if header[0..4] != NES_TAG {
-        panic!("File is not in iNES file format");
+    pub fn load_state(&mut self, state: CartridgeState) -> Result<(), serde_json::Error> {
+        self.mapper.load_state(state.mapper)?;
+        // keep the sizes from the header, the mappers index RAM by them
+        copy_fit(&mut self.mem.prg_ram, &state.prg_ram);
+        if self.mem.chr_ram {
+            copy_fit(&mut self.mem.chr, &state.chr_ram);
+        }
+        Ok(())
     }
-    let mapper = (header[7] & 0b1111_0000) | (header[6] >> 4);
+}
+
+fn copy_fit(dst: &mut [u8], src: &[u8]) {
+    let len = src.len().min(dst.len());
+    dst[..len].copy_from_slice(&src[..len]);
+}
 
-    let ines_ver = (header[7] >> 2) & 0b11;
-    if ines_ver != 0 {
-        panic!("NES2.0 format is not supported");
+// https://www.nesdev.org/wiki/NES_2.0
+fn parse_header(data: &[u8]) -> Result<Header, RomError> {
+    let mut header: [u8; 16] = match data.get(0..16) {
+        Some(header) if header[0..4] == NES_TAG => header.try_into().unwrap(),
+        _ => return Err(RomError::BadMagic),
+    };
+    let nes2 = (header[7] >> 2) & 0b11 == 0b10;
+    // archaic iNES dumps carry garbage such as "DiskDude!" in bytes 7-15, only
+    // bytes 4-6 can be trusted
+    let archaic = (header[7] >> 2) & 0b11 != 0 || header[12..16].iter().any(|&b| b != 0);
+    if !nes2 && archaic {
+        header[7..16].fill(0);
     }
 
     let four_screen = header[6] & 0b1000 != 0;
@@ -117,24 +370,131 @@ fn parse_header(data: &[u8]) -> Header {
         (false, true) => Mirroring::VERTICAL,
         (false, false) => Mirroring::HORIZONTAL,
     };
+    let battery = header[6] & 0b10 != 0;
+    let skip_trainer = header[6] & 0b100 != 0;
 
-    let prg_rom_size = header[4] as usize * PRG_ROM_PAGE_SIZE;
-    let chr_rom_size = header[5] as usize * CHR_ROM_PAGE_SIZE;
+    let mut mapper = ((header[7] & 0b1111_0000) | (header[6] >> 4)) as u16;
+    let mut submapper = 0;
 
-    let skip_trainer = header[6] & 0b100 != 0;
+    let console_type = match header[7] & 0b11 {
+        0 => ConsoleType::NES,
+        1 => ConsoleType::VS_SYSTEM {
+            ppu_type: header[13] & 0x0F,
+            hardware_type: header[13] >> 4,
+        },
+        2 => ConsoleType::PLAYCHOICE_10,
+        _ => ConsoleType::EXTENDED(header[13] & 0x0F),
+    };
+
+    let prg_rom_size;
+    let chr_rom_size;
+    let prg_ram_size;
+    let prg_nvram_size;
+    let chr_ram_size;
+    let chr_nvram_size;
+    let region;
+    let misc_roms;
+    let expansion_device;
+
+    if nes2 {
+        mapper |= ((header[8] & 0x0F) as u16) << 8;
+        submapper = header[8] >> 4;
+
+        prg_rom_size = rom_size(header[4], header[9] & 0x0F, PRG_ROM_PAGE_SIZE);
+        chr_rom_size = rom_size(header[5], header[9] >> 4, CHR_ROM_PAGE_SIZE);
+
+        prg_ram_size = ram_size(header[10] & 0x0F);
+        prg_nvram_size = ram_size(header[10] >> 4);
+        chr_ram_size = ram_size(header[11] & 0x0F);
+        chr_nvram_size = ram_size(header[11] >> 4);
+
+        // multiple-region carts run as NTSC
+        region = match header[12] & 0b11 {
+            1 => Region::PAL,
+            3 => Region::DENDY,
+            _ => Region::NTSC,
+        };
+        misc_roms = header[14] & 0b11;
+        expansion_device = header[15] & 0b11_1111;
+    } else {
+        prg_rom_size = header[4] as usize * PRG_ROM_PAGE_SIZE;
+        chr_rom_size = header[5] as usize * CHR_ROM_PAGE_SIZE;
+
+        // iNES leaves PRG-RAM out, assume the boards that usually carry it do.
+        // a size of 0 infers 8KB for compatibility
+        let has_ram = battery || header[8] != 0 || matches!(mapper, 1 | 4);
+        let ram = if has_ram {
+            (header[8] as usize).max(1) * PRG_RAM_PAGE_SIZE
+        } else {
+            0
+        };
+        (prg_ram_size, prg_nvram_size) = if battery { (0, ram) } else { (ram, 0) };
+        chr_ram_size = if chr_rom_size == 0 {
+            CHR_ROM_PAGE_SIZE
+        } else {
+            0
+        };
+        chr_nvram_size = 0;
+
+        region = if header[9] & 0b1 != 0 {
+            Region::PAL
+        } else {
+            Region::NTSC
+        };
+        misc_roms = 0;
+        expansion_device = 0;
+    }
 
-    let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
-    let chr_rom_start = prg_rom_start + prg_rom_size;
+    if prg_rom_size == 0 {
+        return Err(RomError::UnsupportedFormat("no PRG ROM"));
+    }
 
-    let prg_ram_size = header[8] as usize;
+    let prg_rom_start: usize = 16 + if skip_trainer { 512 } else { 0 };
+    let chr_rom_start = prg_rom_start.saturating_add(prg_rom_size);
 
-    Header {
+    Ok(Header {
+        nes2,
         prg_rom_start,
         prg_rom_size,
         chr_rom_start,
         chr_rom_size,
-        ram_size: prg_ram_size,
+        prg_ram_size,
+        prg_nvram_size,
+        chr_ram_size,
+        chr_nvram_size,
         mapper,
+        submapper,
         screen_mirroring,
+        battery,
+        region,
+        console_type,
+        misc_roms,
+        expansion_device,
+    })
+}
+
+fn slice(data: &[u8], start: usize, size: usize) -> Option<Vec<u8>> {
+    data.get(start..start.checked_add(size)?)
+        .map(|data| data.to_vec())
+}
+
+// a size MSB nibble of $F switches the LSB byte to exponent-multiplier form,
+// EEEE EEMM gives 2^E * (MM * 2 + 1) bytes
+fn rom_size(lsb: u8, msb: u8, page_size: usize) -> usize {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * page_size
+    }
+}
+
+// RAM sizes are shift counts, 64 << n bytes and 0 for none
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
     }
 }
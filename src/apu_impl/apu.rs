@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+
+use super::dmc::Dmc;
+use super::frame_counter::FrameCounter;
+use super::mixer::{Channel, Mixer};
+use super::noise::Noise;
+use super::pulse::Pulse;
+use super::triangle::Triangle;
+use crate::clock::Region;
+
+// 2A03 audio processing unit
+// $4000-$4003  pulse 1
+// $4004-$4007  pulse 2
+// $4008-$400B  triangle
+// $400C-$400F  noise
+// $4010-$4013  DMC
+// $4015        channel enable / status
+// $4017        frame counter
+#[derive(Serialize, Deserialize)]
+pub struct APU {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    cycle: u64,
+    #[serde(skip)]
+    mixer: Mixer,
+}
+
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_counter: FrameCounter::new(),
+            cycle: 0,
+            mixer: Mixer::new(),
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+        self.frame_counter.set_region(region);
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            0x4000..=0x4003 => self.pulse1.write_register(address & 0b11, data),
+            0x4004..=0x4007 => self.pulse2.write_register(address & 0b11, data),
+            0x4008..=0x400B => self.triangle.write_register(address & 0b11, data),
+            0x400C..=0x400F => self.noise.write_register(address & 0b11, data),
+            0x4010..=0x4013 => self.dmc.write_register(address & 0b11, data),
+            // ---D NT21
+            0x4015 => {
+                self.pulse1.set_enabled(data & 0b0000_0001 != 0);
+                self.pulse2.set_enabled(data & 0b0000_0010 != 0);
+                self.triangle.set_enabled(data & 0b0000_0100 != 0);
+                self.noise.set_enabled(data & 0b0000_1000 != 0);
+                self.dmc.set_enabled(data & 0b0001_0000 != 0);
+            }
+            0x4017 => self.frame_counter.write(data, self.cycle & 1 == 1),
+            _ => {}
+        }
+    }
+
+    // IF-D NT21
+    // |||| ||||
+    // |||| |||+- Pulse 1 length counter > 0
+    // |||| ||+-- Pulse 2 length counter > 0
+    // |||| |+--- Triangle length counter > 0
+    // |||| +---- Noise length counter > 0
+    // |||+------ DMC bytes remaining > 0
+    // ||+------- Open bus
+    // |+-------- Frame interrupt
+    // +--------- DMC interrupt
+    pub fn read_status(&mut self) -> u8 {
+        let status = (self.pulse1.is_active() as u8)
+            | (self.pulse2.is_active() as u8) << 1
+            | (self.triangle.is_active() as u8) << 2
+            | (self.noise.is_active() as u8) << 3
+            | (self.dmc.is_active() as u8) << 4
+            | (self.frame_counter.interrupt() as u8) << 6
+            | (self.dmc.interrupt() as u8) << 7;
+        self.frame_counter.clear_interrupt();
+        status
+    }
+
+    // level-triggered IRQ output of the frame counter and the DMC
+    pub fn irq(&self) -> bool {
+        self.frame_counter.interrupt() || self.dmc.interrupt()
+    }
+
+    // advance one CPU cycle
+    pub fn tick(&mut self) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.cycle & 1 == 1 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+
+        let clock = self.frame_counter.tick();
+        if clock.quarter {
+            self.pulse1.clock_quarter_frame();
+            self.pulse2.clock_quarter_frame();
+            self.triangle.clock_quarter_frame();
+            self.noise.clock_quarter_frame();
+        }
+        if clock.half {
+            self.pulse1.clock_half_frame();
+            self.pulse2.clock_half_frame();
+            self.triangle.clock_half_frame();
+            self.noise.clock_half_frame();
+        }
+
+        self.cycle = self.cycle.wrapping_add(1);
+    }
+
+    // address of the sample byte the DMC needs from CPU memory, if any
+    pub fn dmc_fetch_address(&self) -> Option<u16> {
+        self.dmc.fetch_address()
+    }
+
+    pub fn dmc_fill(&mut self, data: u8) {
+        self.dmc.fill_sample_buffer(data);
+    }
+
+    pub fn set_channel_volume(&mut self, channel: Channel, volume: f32) {
+        self.mixer.set_volume(channel, volume);
+    }
+
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.mixer.set_muted(channel, muted);
+    }
+
+    pub fn set_channel_solo(&mut self, channel: Channel, solo: bool) {
+        self.mixer.set_solo(channel, solo);
+    }
+
+    // non-linear mixer output in the range 0.0..1.0
+    pub fn output(&self) -> f32 {
+        self.mixer.mix(
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        )
+    }
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+
+use crate::clock::Region;
+
+pub struct FrameClock {
+    pub quarter: bool,
+    pub half: bool,
+}
+
+// frame sequencer on $4017, drives envelopes, linear counters, length counters and sweeps
+#[derive(Serialize, Deserialize)]
+pub struct FrameCounter {
+    region: Region,
+    cycle: u32,
+    five_step: bool,
+    irq_inhibit: bool,
+    interrupt: bool,
+    // CPU cycles left until a $4017 write restarts the sequence
+    reset_delay: Option<u8>,
+}
+
+impl FrameCounter {
+    pub fn new() -> Self {
+        FrameCounter {
+            region: Region::default(),
+            cycle: 0,
+            five_step: false,
+            irq_inhibit: false,
+            interrupt: false,
+            reset_delay: None,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    // MI-- ----
+    // ||
+    // |+-------- IRQ inhibit flag, setting it also clears the frame interrupt
+    // +--------- Sequencer mode (0: 4-step, 1: 5-step)
+    //
+    // the sequence restarts 3 CPU cycles after a write made during an APU
+    // cycle, 4 cycles after one made between APU cycles
+    pub fn write(&mut self, data: u8, odd_cycle: bool) {
+        self.five_step = data & 0b1000_0000 != 0;
+        self.irq_inhibit = data & 0b0100_0000 != 0;
+        if self.irq_inhibit {
+            self.interrupt = false;
+        }
+        self.reset_delay = Some(if odd_cycle { 4 } else { 3 });
+    }
+
+    pub fn interrupt(&self) -> bool {
+        self.interrupt
+    }
+
+    // reading $4015 acknowledges the frame interrupt
+    pub fn clear_interrupt(&mut self) {
+        self.interrupt = false;
+    }
+
+    // clocked every CPU cycle
+    pub fn tick(&mut self) -> FrameClock {
+        self.cycle += 1;
+        let steps = self.steps();
+        let mut clock = FrameClock {
+            quarter: false,
+            half: false,
+        };
+
+        if self.cycle == steps[0] || self.cycle == steps[2] {
+            clock.quarter = true;
+        } else if self.cycle == steps[1] {
+            clock.quarter = true;
+            clock.half = true;
+        }
+
+        if self.five_step {
+            if self.cycle == steps[4] {
+                clock.quarter = true;
+                clock.half = true;
+            } else if self.cycle > steps[4] {
+                self.cycle = 0;
+            }
+        } else {
+            if self.cycle == steps[3] {
+                clock.quarter = true;
+                clock.half = true;
+            }
+            // the interrupt flag is raised on the last three cycles of the sequence
+            if !self.irq_inhibit && self.cycle + 1 >= steps[3] && self.cycle <= steps[3] + 1 {
+                self.interrupt = true;
+            }
+            if self.cycle > steps[3] {
+                self.cycle = 0;
+            }
+        }
+
+        if let Some(delay) = self.reset_delay {
+            if delay > 1 {
+                self.reset_delay = Some(delay - 1);
+            } else {
+                self.reset_delay = None;
+                self.cycle = 0;
+                // entering 5-step mode clocks the units immediately
+                if self.five_step {
+                    clock.quarter = true;
+                    clock.half = true;
+                }
+            }
+        }
+        clock
+    }
+
+    // CPU cycles of each step after the sequence starts, the fourth step ends
+    // the 4-step sequence and is skipped by the 5-step one
+    fn steps(&self) -> [u32; 5] {
+        match self.region {
+            Region::NTSC | Region::DENDY => [7457, 14913, 22371, 29829, 37281],
+            Region::PAL => [8313, 16627, 24939, 33253, 41565],
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use wasm_bindgen::prelude::wasm_bindgen;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Pulse1 = 0,
+    Pulse2 = 1,
+    Triangle = 2,
+    Noise = 3,
+    Dmc = 4,
+}
+
+const CHANNELS: usize = 5;
+
+// per-channel volume, mute and solo applied to the channel outputs before the
+// non-linear mix, a debugging aid that is not part of the emulated state
+pub struct Mixer {
+    volume: [f32; CHANNELS],
+    muted: [bool; CHANNELS],
+    solo: [bool; CHANNELS],
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Mixer {
+            volume: [1.0; CHANNELS],
+            muted: [false; CHANNELS],
+            solo: [false; CHANNELS],
+        }
+    }
+
+    pub fn set_volume(&mut self, channel: Channel, volume: f32) {
+        self.volume[channel as usize] = volume.max(0.0);
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+
+    // while any channel is soloed only the soloed channels are heard
+    pub fn set_solo(&mut self, channel: Channel, solo: bool) {
+        self.solo[channel as usize] = solo;
+    }
+
+    pub fn mix(&self, pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        let pulse =
+            pulse1 as f32 * self.gain(Channel::Pulse1) + pulse2 as f32 * self.gain(Channel::Pulse2);
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+
+        let tnd = triangle as f32 * self.gain(Channel::Triangle) / 8227.0
+            + noise as f32 * self.gain(Channel::Noise) / 12241.0
+            + dmc as f32 * self.gain(Channel::Dmc) / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out
+    }
+
+    fn gain(&self, channel: Channel) -> f32 {
+        let index = channel as usize;
+        let soloing = self.solo.iter().any(|&solo| solo);
+        if self.muted[index] || (soloing && !self.solo[index]) {
+            0.0
+        } else {
+            self.volume[index]
+        }
+    }
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,9 @@
+pub mod apu;
+mod dmc;
+mod envelope;
+mod frame_counter;
+mod length_counter;
+pub mod mixer;
+mod noise;
+mod pulse;
+mod triangle;
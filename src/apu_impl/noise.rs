@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+
+use super::{envelope::Envelope, length_counter::LengthCounter};
+use crate::clock::Region;
+
+// timer periods in CPU cycles
+const NTSC_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+// $400C-$400F
+#[derive(Serialize, Deserialize)]
+pub struct Noise {
+    region: Region,
+    mode: bool,
+    period_index: u8,
+    timer: u16,
+    // 15-bit linear feedback shift register
+    shift_register: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            region: Region::default(),
+            mode: false,
+            period_index: 0,
+            timer: 0,
+            shift_register: 1,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            // --LC VVVV
+            0 => {
+                self.length.set_halt(data & 0b0010_0000 != 0);
+                self.envelope.write(data);
+            }
+            1 => {}
+            // M--- PPPP
+            2 => {
+                self.mode = data & 0b1000_0000 != 0;
+                self.period_index = data & 0b1111;
+            }
+            // LLLL L---
+            3 => {
+                self.length.load(data >> 3);
+                self.envelope.restart();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length.set_enabled(enabled);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.length.is_active()
+    }
+
+    // clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period() - 1;
+            // mode 1 taps bit 6 for the short, metallic sequence
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 1 == 1 || !self.length.is_active() {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+
+    fn period(&self) -> u16 {
+        match self.region {
+            Region::NTSC | Region::DENDY => NTSC_PERIODS[self.period_index as usize],
+            Region::PAL => PAL_PERIODS[self.period_index as usize],
+        }
+    }
+}
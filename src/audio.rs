@@ -0,0 +1,186 @@
+use std::f64::consts::PI;
+
+// taps of the band-limited step and the number of sub-sample positions it is tabulated for
+const KERNEL_WIDTH: usize = 16;
+const KERNEL_PHASES: usize = 64;
+// the host may nudge the rate by at most half a percent
+const MAX_RATE_ADJUST: f64 = 0.005;
+// cutoff of the DC-blocking high-pass, the NES has one at about 90Hz
+const HIGH_PASS_HZ: f64 = 90.0;
+// seconds of audio kept when the host stops pulling samples
+const MAX_BUFFERED_SECONDS: usize = 1;
+
+// band-limited resampler from the CPU clock to a host sample rate
+//
+// every change of the input amplitude is recorded as a windowed-sinc impulse at
+// its exact sub-sample position in a delta buffer, reading integrates the
+// deltas into band-limited steps, so no aliasing is produced whatever the rate
+pub struct Resampler {
+    clock_rate: f64,
+    sample_rate: u32,
+    rate_adjust: f64,
+    // output samples per input clock
+    step: f64,
+    // position of the next input clock in the delta buffer
+    position: f64,
+    deltas: Vec<f32>,
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+    amplitude: f32,
+    integrator: f32,
+    high_pass_factor: f32,
+    high_pass_input: f32,
+    high_pass_output: f32,
+}
+
+impl Resampler {
+    pub fn new(clock_rate: f64, sample_rate: u32) -> Self {
+        let mut resampler = Resampler {
+            clock_rate,
+            sample_rate,
+            rate_adjust: 1.0,
+            step: 0.0,
+            position: 0.0,
+            deltas: vec![0.0; KERNEL_WIDTH],
+            kernel: build_kernel(),
+            amplitude: 0.0,
+            integrator: 0.0,
+            high_pass_factor: 0.0,
+            high_pass_input: 0.0,
+            high_pass_output: 0.0,
+        };
+        resampler.update_step();
+        resampler
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.update_step();
+    }
+
+    pub fn set_clock_rate(&mut self, clock_rate: f64) {
+        self.clock_rate = clock_rate;
+        self.update_step();
+    }
+
+    // dynamic rate control: a ratio slightly above 1.0 produces more samples per
+    // emulated second, letting the host keep its audio queue level while video
+    // runs at the display's refresh rate
+    pub fn set_rate_adjust(&mut self, ratio: f64) {
+        self.rate_adjust = ratio.clamp(1.0 - MAX_RATE_ADJUST, 1.0 + MAX_RATE_ADJUST);
+        self.update_step();
+    }
+
+    // advance one input clock with the given amplitude
+    pub fn tick(&mut self, amplitude: f32) {
+        let delta = amplitude - self.amplitude;
+        if delta != 0.0 {
+            self.amplitude = amplitude;
+            self.add_delta(delta);
+        }
+        self.position += self.step;
+
+        let limit = self.sample_rate as usize * MAX_BUFFERED_SECONDS;
+        if self.position as usize > limit {
+            // nobody is listening, drop the oldest samples
+            self.skip(self.position as usize - limit);
+        }
+    }
+
+    // number of output samples ready to be read
+    pub fn samples_available(&self) -> usize {
+        self.position as usize
+    }
+
+    pub fn read_samples(&mut self, out: &mut [f32]) -> usize {
+        let count = out.len().min(self.samples_available());
+        for (i, sample) in out.iter_mut().take(count).enumerate() {
+            *sample = self.output(i);
+        }
+        self.consume(count);
+        count
+    }
+
+    pub fn read_samples_i16(&mut self, out: &mut [i16]) -> usize {
+        let count = out.len().min(self.samples_available());
+        for (i, sample) in out.iter_mut().take(count).enumerate() {
+            *sample = (self.output(i).clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        }
+        self.consume(count);
+        count
+    }
+
+    fn update_step(&mut self) {
+        let sample_rate = self.sample_rate as f64 * self.rate_adjust;
+        self.step = sample_rate / self.clock_rate;
+        self.high_pass_factor = (1.0 / (1.0 + 2.0 * PI * HIGH_PASS_HZ / sample_rate)) as f32;
+    }
+
+    fn add_delta(&mut self, delta: f32) {
+        let index = self.position as usize;
+        let phase = (self.position.fract() * KERNEL_PHASES as f64) as usize;
+        let end = index + KERNEL_WIDTH;
+        if self.deltas.len() < end {
+            self.deltas.resize(end, 0.0);
+        }
+        for (slot, tap) in self.deltas[index..end].iter_mut().zip(&self.kernel[phase]) {
+            *slot += delta * tap;
+        }
+    }
+
+    // integrate one delta into a step and remove the DC offset
+    fn output(&mut self, index: usize) -> f32 {
+        // slots past the end of the buffer never received a delta
+        self.integrator += self.deltas.get(index).copied().unwrap_or(0.0);
+        let output = self.high_pass_factor
+            * (self.high_pass_output + self.integrator - self.high_pass_input);
+        self.high_pass_input = self.integrator;
+        self.high_pass_output = output;
+        output
+    }
+
+    fn skip(&mut self, count: usize) {
+        for i in 0..count {
+            self.output(i);
+        }
+        self.consume(count);
+    }
+
+    fn consume(&mut self, count: usize) {
+        self.deltas.drain(..count.min(self.deltas.len()));
+        if self.deltas.len() < KERNEL_WIDTH {
+            self.deltas.resize(KERNEL_WIDTH, 0.0);
+        }
+        self.position -= count as f64;
+    }
+}
+
+// windowed-sinc impulses for each sub-sample phase, each normalised to unit gain
+fn build_kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+    // just below the output Nyquist frequency
+    let cutoff = 0.45;
+    let center = (KERNEL_WIDTH / 2) as f64;
+    (0..KERNEL_PHASES)
+        .map(|phase| {
+            let offset = phase as f64 / KERNEL_PHASES as f64;
+            let mut taps = [0.0; KERNEL_WIDTH];
+            for (k, tap) in taps.iter_mut().enumerate() {
+                let x = k as f64 - center - offset;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (2.0 * PI * cutoff * x).sin() / (2.0 * PI * cutoff * x)
+                };
+                // Blackman window over the kernel width
+                let n = (x + center) / KERNEL_WIDTH as f64;
+                let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+                *tap = sinc * window;
+            }
+            let sum: f64 = taps.iter().sum();
+            taps.map(|tap| (tap / sum) as f32)
+        })
+        .collect()
+}
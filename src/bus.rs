@@ -1,24 +1,158 @@
-use std::sync::mpsc::{Receiver, Sender};
+use std::{cell::RefCell, rc::Rc};
 
+use serde::{Deserialize, Serialize};
+use serde_big_array::BigArray;
+
+use crate::{
+    apu_impl::apu::APU,
+    joypad::Joypad,
+    ppu_impl::ppu::PPU,
+    ROM::{RomError, ROM},
+};
+
+// CPU memory map
+// $0000-$07FF  2KB internal RAM, mirrored up to $1FFF
+// $2000-$2007  PPU registers, mirrored every 8 bytes up to $3FFF
+// $4000-$4017  APU and I/O registers
+// $4018-$401F  APU and I/O functionality that is normally disabled
+// $4020-$FFFF  cartridge space
+#[derive(Serialize, Deserialize)]
 pub struct Bus {
-    pub sender: Sender<(u16, u8)>,
-    pub receiver: Receiver<(u16, u8)>,
+    #[serde(with = "BigArray")]
+    pub ram: [u8; 2048],
+    pub ppu: PPU,
+    pub apu: APU,
+    // the cartridge, shared with the PPU
+    #[serde(skip)]
+    pub rom: Option<Rc<RefCell<ROM>>>,
+    pub joypad1: Joypad,
+    pub joypad2: Joypad,
+    // set by a write to $4014, the CPU halts while the copy runs
+    oam_dma: bool,
+    // CPU cycles stolen by DMC sample fetches, not yet taken by the CPU
+    dmc_stall: usize,
+    // last value seen on the data bus, returned for unmapped reads
+    data_bus: u8,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Bus {
+            ram: [0; 2048],
+            ppu: PPU::new(),
+            apu: APU::new(),
+            rom: None,
+            joypad1: Joypad::new(),
+            joypad2: Joypad::new(),
+            oam_dma: false,
+            dmc_stall: 0,
+            data_bus: 0,
+        }
+    }
+
+    pub fn load_rom(&mut self, data: Vec<u8>) -> Result<(), RomError> {
+        let rom = Rc::new(RefCell::new(ROM::new(data)?));
+        self.ppu.set_cartridge(rom.clone());
+        self.rom = Some(rom);
+        Ok(())
+    }
 }
 
 impl Bus {
-    pub fn new(sender: Sender<(u16, u8)>, receiver: Receiver<(u16, u8)>) -> Self {
-        Bus { sender, receiver }
+    pub fn poll_nmi(&mut self) -> bool {
+        self.ppu.poll_nmi()
+    }
+
+    // return true once after an OAM DMA so the CPU can account for the stall
+    pub fn take_oam_dma(&mut self) -> bool {
+        std::mem::take(&mut self.oam_dma)
     }
 
-    pub fn receive_data(&self, expect_addr: u16) -> u8 {
-        let data = self.receiver.recv().expect("receive error data!");
-        if data.0 != expect_addr {
-            panic!("receive wrong addr data!")
+    pub fn poll_irq(&self) -> bool {
+        self.apu.irq() || self.rom.as_ref().is_some_and(|rom| rom.borrow().irq())
+    }
+
+    pub fn take_dmc_stall(&mut self) -> usize {
+        std::mem::take(&mut self.dmc_stall)
+    }
+
+    // advance the APU and the cartridge one CPU cycle and service the DMC memory reader
+    pub fn clock(&mut self) {
+        self.apu.tick();
+        if let Some(rom) = self.rom.as_ref() {
+            rom.borrow_mut().cpu_cycle();
+        }
+        if let Some(address) = self.apu.dmc_fetch_address() {
+            let data = self.read(address);
+            self.apu.dmc_fill(data);
+            self.dmc_stall += 4;
         }
-        data.1
     }
 
-    pub fn send_data(&self, addr: u16, data: u8) {
-        self.sender.send((addr, data)).expect("send error data!");
+    // copy page $XX00-$XXFF into OAM through $2004
+    fn oam_dma(&mut self, page: u8) {
+        let base = (page as u16) << 8;
+        for offset in 0..=0xFF {
+            let data = self.read(base | offset);
+            self.ppu.write_register(0x2004, data);
+        }
+        self.oam_dma = true;
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    pub fn read(&mut self, address: u16) -> u8 {
+        let data = match address {
+            0x0000..=0x1FFF => self.ram[(address & 0x07FF) as usize],
+            0x2000..=0x3FFF => self.ppu.read_register(address & 0b0010_0000_0000_0111),
+            // bit 5 of the status is open bus
+            0x4015 => (self.data_bus & 0b0010_0000) | self.apu.read_status(),
+            // controllers only drive the low bits, the rest is open bus
+            0x4016 => (self.data_bus & 0b1110_0000) | self.joypad1.read(),
+            0x4017 => (self.data_bus & 0b1110_0000) | self.joypad2.read(),
+            // the remaining APU registers are write-only
+            0x4000..=0x401F => self.data_bus,
+            0x4020..=0xFFFF => self
+                .rom
+                .as_ref()
+                .expect("not load rom!")
+                .borrow_mut()
+                .cpu_read(address)
+                .unwrap_or(self.data_bus),
+        };
+        self.data_bus = data;
+        data
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        self.data_bus = data;
+        match address {
+            0x0000..=0x1FFF => {
+                self.ram[(address & 0x07FF) as usize] = data;
+            }
+            0x2000..=0x3FFF => self
+                .ppu
+                .write_register(address & 0b0010_0000_0000_0111, data),
+            0x4014 => self.oam_dma(data),
+            0x4016 => {
+                self.joypad1.write(data);
+                self.joypad2.write(data);
+            }
+            // $4017 reads the second controller but writes the APU frame counter
+            0x4000..=0x4017 => self.apu.write_register(address, data),
+            0x4018..=0x401F => {}
+            0x4020..=0xFFFF => self
+                .rom
+                .as_ref()
+                .expect("not load rom!")
+                .borrow_mut()
+                .cpu_write(address, data),
+        }
     }
 }
@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Region {
+    #[default]
+    NTSC,
+    PAL,
+    DENDY,
+}
+
+impl Region {
+    // master clock frequency in Hz
+    pub fn master_clock_rate(&self) -> u64 {
+        match self {
+            Region::NTSC => 21_477_272,
+            Region::PAL | Region::DENDY => 26_601_712,
+        }
+    }
+
+    // master cycles per CPU cycle
+    pub fn cpu_divider(&self) -> u64 {
+        match self {
+            Region::NTSC => 12,
+            Region::PAL => 16,
+            Region::DENDY => 15,
+        }
+    }
+
+    // master cycles per PPU dot
+    pub fn ppu_divider(&self) -> u64 {
+        match self {
+            Region::NTSC => 4,
+            Region::PAL | Region::DENDY => 5,
+        }
+    }
+
+    // scanlines per frame, including the pre-render scanline
+    pub fn scanlines(&self) -> u16 {
+        match self {
+            Region::NTSC => 262,
+            Region::PAL | Region::DENDY => 312,
+        }
+    }
+
+    // first scanline of vertical blank
+    pub fn vblank_scanline(&self) -> u16 {
+        match self {
+            Region::NTSC | Region::PAL => 241,
+            Region::DENDY => 291,
+        }
+    }
+}
+
+pub struct Tick {
+    pub cpu: bool,
+    pub ppu: bool,
+}
+
+// master clock scheduler, hands out CPU cycles and PPU dots at the region's ratio
+#[derive(Serialize, Deserialize)]
+pub struct Clock {
+    region: Region,
+    master_cycles: u64,
+    next_cpu_cycle: u64,
+    next_ppu_cycle: u64,
+}
+
+impl Clock {
+    pub fn new(region: Region) -> Self {
+        Clock {
+            region,
+            master_cycles: 0,
+            next_cpu_cycle: 0,
+            next_ppu_cycle: 0,
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    pub fn master_cycles(&self) -> u64 {
+        self.master_cycles
+    }
+
+    // advance to the next CPU cycle or PPU dot scheduled before `target`
+    pub fn advance(&mut self, target: u64) -> Option<Tick> {
+        let next = self.next_cpu_cycle.min(self.next_ppu_cycle);
+        if next >= target {
+            self.master_cycles = self.master_cycles.max(target);
+            return None;
+        }
+        self.master_cycles = next;
+
+        let cpu = self.next_cpu_cycle == next;
+        if cpu {
+            self.next_cpu_cycle += self.region.cpu_divider();
+        }
+        let ppu = self.next_ppu_cycle == next;
+        if ppu {
+            self.next_ppu_cycle += self.region.ppu_divider();
+        }
+        Some(Tick { cpu, ppu })
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new(Region::default())
+    }
+}
@@ -4,7 +4,7 @@ use crate::{
     consts::{IRQ_ADDR, NMI_ADDR, RESET_ADDR},
     memory::CpuMemory,
     register::{Flags, Register, RegisterWork},
-    ROM::ROM,
+    ROM::{CartridgeState, RomError},
 };
 
 #[allow(unused_macros)]
@@ -105,6 +105,7 @@ struct Operation {
     instruction_type: InstructionTypes,
     cycle: u8,
     addressing_mode: AddressingModes,
+    #[allow(dead_code)]
     opc: u8,
 }
 
@@ -144,18 +145,33 @@ impl CPU {
         }
     }
 
-    pub fn load(self, data: Vec<u8>) -> Self {
-        let mut save_data: CPU = serde_json::from_slice(&data).expect("decode archive failed!");
-        save_data.mem.rom = self.mem.rom;
-        save_data
+    // restore a save made by `save` for the loaded cartridge, a corrupt save or one
+    // made with another mapper is rejected
+    pub fn load(&mut self, data: &[u8]) -> Result<(), serde_json::Error> {
+        let (mut save_data, cartridge): (CPU, Option<CartridgeState>) =
+            serde_json::from_slice(data)?;
+        if let (Some(rom), Some(state)) = (self.mem.bus.rom.as_ref(), cartridge) {
+            rom.borrow_mut().load_state(state)?;
+        }
+        save_data.mem.bus.rom = self.mem.bus.rom.take();
+        save_data.mem.bus.ppu.mem.rom = self.mem.bus.ppu.mem.rom.take();
+        *self = save_data;
+        Ok(())
     }
 
+    // the cartridge is shared by both buses, its state is stored once next to the CPU's
     pub fn save(&mut self) -> Vec<u8> {
-        serde_json::to_vec(self).unwrap()
+        let cartridge = self
+            .mem
+            .bus
+            .rom
+            .as_ref()
+            .map(|rom| rom.borrow().save_state());
+        serde_json::to_vec(&(&*self, cartridge)).unwrap()
     }
 
-    pub fn load_rom(&mut self, data: Vec<u8>) {
-        self.mem.rom = Some(ROM::new(data, "cpu"));
+    pub fn load_rom(&mut self, data: Vec<u8>) -> Result<(), RomError> {
+        self.mem.bus.load_rom(data)
     }
 }
 
@@ -260,10 +276,11 @@ impl CPU {
 
         self.register_sp
             .stack_push_word(&mut self.mem, self.program_counter.data());
-        self.register_p.stack_push_byte(
+        self.register_sp.stack_push_byte(
             &mut self.mem,
             (self.register_p.data() | Flags::U as u8) & !(Flags::B as u8),
         );
+        self.register_p.set_flag(Flags::I, true);
         #[allow(const_item_mutation)]
         self.program_counter.set_data(self.mem.loadw(&mut IRQ_ADDR));
         self.defer_cycles = self.defer_cycles.wrapping_add(7);
@@ -272,10 +289,11 @@ impl CPU {
     pub fn nmi(&mut self) {
         self.register_sp
             .stack_push_word(&mut self.mem, self.program_counter.data());
-        self.register_p.stack_push_byte(
+        self.register_sp.stack_push_byte(
             &mut self.mem,
             (self.register_p.data() | Flags::U as u8) & !(Flags::B as u8),
         );
+        self.register_p.set_flag(Flags::I, true);
         #[allow(const_item_mutation)]
         self.program_counter.set_data(self.mem.loadw(&mut NMI_ADDR));
         self.defer_cycles = self.defer_cycles.wrapping_add(7);
@@ -301,16 +319,26 @@ impl CPU {
         }
     }
 
-    pub fn clock(&mut self) {
+    // return true when a new instruction or interrupt starts on this cycle
+    pub fn clock(&mut self) -> bool {
         self.now_cycles = self.now_cycles.wrapping_add(1);
         #[cfg(feature = "wasm-debug")]
         wasmLog!("now_cycles: {}", self.now_cycles);
+        self.defer_cycles += self.mem.bus.take_dmc_stall();
         if self.defer_cycles > 0 {
             self.defer_cycles -= 1;
         }
         if self.defer_cycles == 0 {
-            self.step();
+            if self.mem.bus.poll_nmi() {
+                self.nmi();
+            } else if self.mem.bus.poll_irq() && !self.register_p.check_flag(Flags::I) {
+                self.irq();
+            } else {
+                self.step();
+            }
+            return true;
         }
+        false
     }
 
     fn step(&mut self) {
@@ -327,6 +355,12 @@ impl CPU {
             wasmLog!("op: {:?}", op);
         }
         self.exec(op);
+
+        if self.mem.bus.take_oam_dma() {
+            // 513 cycles, plus one more when the DMA has to wait for an even cycle
+            let start = self.now_cycles.wrapping_add(self.defer_cycles);
+            self.defer_cycles = self.defer_cycles.wrapping_add(513 + start % 2);
+        }
     }
 }
 
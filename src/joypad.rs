@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+bitflags::bitflags! {
+    // buttons in the order the controller shifts them out
+    #[derive(Serialize, Deserialize)]
+    pub struct JoypadButton: u8 {
+        const BUTTON_A = 0b00000001;
+        const BUTTON_B = 0b00000010;
+        const SELECT   = 0b00000100;
+        const START    = 0b00001000;
+        const UP       = 0b00010000;
+        const DOWN     = 0b00100000;
+        const LEFT     = 0b01000000;
+        const RIGHT    = 0b10000000;
+    }
+}
+
+// standard controller: a parallel-in, serial-out shift register
+#[derive(Serialize, Deserialize)]
+pub struct Joypad {
+    strobe: bool,
+    // buttons latched when strobe falls, shifted out one per read
+    shift: u8,
+    button_status: JoypadButton,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            shift: 0,
+            button_status: JoypadButton::empty(),
+        }
+    }
+
+    pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
+        self.button_status.set(button, pressed);
+    }
+
+    pub fn buttons(&self) -> JoypadButton {
+        self.button_status
+    }
+
+    // while strobe is high the buttons are continuously reloaded, the 1 -> 0
+    // edge latches them so a report can't mix two button states
+    pub fn write(&mut self, data: u8) {
+        let strobe = data & 1 == 1;
+        if self.strobe && !strobe {
+            self.shift = self.button_status.bits();
+        }
+        self.strobe = strobe;
+    }
+
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.button_status.bits() & 1;
+        }
+        let response = self.shift & 1;
+        // an official controller shifts in 1s, reads after the eighth return 1
+        self.shift = (self.shift >> 1) | 0b1000_0000;
+        response
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,22 +1,31 @@
 #[allow(non_snake_case)]
 pub mod ROM;
+pub mod apu_impl;
+pub mod audio;
 mod bus;
+pub mod clock;
 #[allow(non_snake_case)]
 pub mod consts;
 pub mod cpu;
+pub mod joypad;
 mod memory;
+pub mod nes;
 pub mod ppu_impl;
 mod register;
 mod utils;
+pub mod wav;
 
 use std::sync::mpsc::{Receiver, Sender};
 
-use cpu::CPU;
-use ppu_impl::ppu::PPU;
+use apu_impl::mixer::Channel;
+use gloo::storage::{LocalStorage, Storage};
+use joypad::JoypadButton;
+use nes::Nes;
 use wasm_bindgen::{
     prelude::{wasm_bindgen, Closure},
-    JsCast,
+    JsCast, JsError,
 };
+use ROM::RomError;
 
 #[allow(unused_macros)]
 macro_rules! wasmLog {
@@ -29,74 +38,71 @@ macro_rules! wasmLog {
 pub struct BackEnd {
     width: usize,
     height: usize,
-    screen: Vec<u8>,
-    cpu: CPU,
-    ppu: PPU,
-    action_receiver: Receiver<u8>,
+    nes: Nes,
+    action_receiver: Receiver<(JoypadButton, bool)>,
+    // localStorage key of the cart's battery RAM and the copy last written there
+    save_key: String,
+    saved_battery_ram: Option<Vec<u8>>,
+    frames: u32,
 }
 
+// frames between checks for battery RAM changes to persist
+const SAVE_INTERVAL: u32 = 60;
+
 impl BackEnd {
     fn handle_user_input(&mut self) {
-        while let Ok(action) = self.action_receiver.try_recv() {
-            match action {
-                1 => {
-                    self.cpu.mem.storeb(0xff, 0x77);
-                }
-                2 => {
-                    self.cpu.mem.storeb(0xff, 0x73);
-                }
-                3 => {
-                    self.cpu.mem.storeb(0xff, 0x61);
-                }
-                4 => {
-                    self.cpu.mem.storeb(0xff, 0x64);
-                }
-                _ => {}
-            }
+        while let Ok((button, pressed)) = self.action_receiver.try_recv() {
+            self.nes.set_button(1, button, pressed);
         }
     }
 
-    fn construct(data: Vec<u8>) -> Self {
-        let mut cpu = CPU::new();
-        cpu.load_rom(data.clone());
-        cpu.reset();
-        let mut ppu = PPU::new();
-        ppu.load_rom(data);
+    fn construct(data: Vec<u8>) -> Result<Self, RomError> {
+        let save_key = format!("rust-nes-{:016x}.sav", fnv1a(&data));
+        let mut nes = Nes::new(data)?;
+        if nes.battery_ram().is_some() {
+            if let Ok(ram) = LocalStorage::get::<Vec<u8>>(&save_key) {
+                nes.load_battery_ram(&ram);
+            }
+        }
+        let saved_battery_ram = nes.battery_ram();
 
         let (action_sender, action_receiver) = std::sync::mpsc::channel();
         add_key_board_listener(action_sender);
 
         let width = consts::WIDTH;
         let height = consts::HEIGHT;
-        BackEnd {
+        Ok(BackEnd {
             width,
             height,
-            screen: vec![0; width * height * 3],
-            cpu,
-            ppu,
+            nes,
             action_receiver,
-        }
+            save_key,
+            saved_battery_ram,
+            frames: 0,
+        })
     }
-}
 
-impl Default for BackEnd {
-    fn default() -> Self {
-        Self::new()
+    fn persist_battery_ram(&mut self) {
+        let ram = self.nes.battery_ram();
+        if ram != self.saved_battery_ram {
+            if let Some(ram) = ram.as_ref() {
+                // out of quota, keep the game running and retry next time
+                if LocalStorage::set(&self.save_key, ram).is_err() {
+                    return;
+                }
+            }
+            self.saved_battery_ram = ram;
+        }
     }
 }
 
 #[wasm_bindgen]
 impl BackEnd {
-    pub fn new() -> Self {
-        utils::set_panic_hook();
-        let data: Vec<u8> = vec![];
-        BackEnd::construct(data)
-    }
-
-    pub fn new_with_data(data: &[u8]) -> Self {
+    // throws a JS Error describing why the file could not be loaded
+    pub fn new_with_data(data: &[u8]) -> Result<BackEnd, JsError> {
         utils::set_panic_hook();
         let data: Vec<u8> = data.into();
-        BackEnd::construct(data)
+        Ok(BackEnd::construct(data)?)
     }
 
     pub fn width(&self) -> u32 {
@@ -108,34 +114,98 @@ impl BackEnd {
     }
 
     pub fn screen(&self) -> *const u8 {
-        self.screen.as_ptr()
+        self.nes.frame().data.as_ptr()
     }
 
     pub fn run(&mut self) {
-        loop {
-            self.handle_user_input();
-            self.cpu.clock();
+        self.handle_user_input();
+        self.nes.run_frame();
+        self.frames += 1;
+        if self.frames == SAVE_INTERVAL {
+            self.frames = 0;
+            self.persist_battery_ram();
         }
     }
+
+    // press or release buttons of controller 1 or 2 from a gamepad or touch
+    // front end, `button` takes the JoypadButton bits: A, B, Select, Start,
+    // Up, Down, Left, Right from bit 0
+    pub fn set_button(&mut self, player: u8, button: u8, pressed: bool) {
+        self.nes
+            .set_button(player, JoypadButton::from_bits_truncate(button), pressed);
+    }
+
+    // battery RAM as a .sav file, undefined for carts without a battery
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        self.nes.battery_ram()
+    }
+
+    // import a .sav file, it is also persisted to localStorage
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        self.nes.load_battery_ram(data);
+        self.persist_battery_ram();
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.nes.set_sample_rate(sample_rate);
+    }
+
+    pub fn set_audio_rate_adjust(&mut self, ratio: f64) {
+        self.nes.set_audio_rate_adjust(ratio);
+    }
+
+    pub fn set_channel_volume(&mut self, channel: Channel, volume: f32) {
+        self.nes.set_channel_volume(channel, volume);
+    }
+
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.nes.set_channel_muted(channel, muted);
+    }
+
+    pub fn set_channel_solo(&mut self, channel: Channel, solo: bool) {
+        self.nes.set_channel_solo(channel, solo);
+    }
+
+    // all audio produced since the last call, as a Float32Array
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        let mut samples = vec![0.0; self.nes.samples_available()];
+        self.nes.read_samples(&mut samples);
+        samples
+    }
 }
 
-fn add_key_board_listener(sender: Sender<u8>) {
+fn add_key_board_listener(sender: Sender<(JoypadButton, bool)>) {
     let window = web_sys::window().expect("no global `window` exists");
     let document = window.document().expect("should have a document on window");
-    let call_back = Closure::wrap(Box::new(move |event: web_sys::KeyboardEvent| {
-        let key = event.key();
-        let action = match key.as_str() {
-            "w" => 1,
-            "s" => 2,
-            "a" => 3,
-            "d" => 4,
-            _ => 0,
-        };
-        sender.send(action).unwrap();
-    }) as Box<dyn FnMut(_)>);
-
-    document
-        .add_event_listener_with_callback("keydown", call_back.as_ref().unchecked_ref())
-        .unwrap();
-    call_back.forget();
+
+    for (event_name, pressed) in [("keydown", true), ("keyup", false)] {
+        let sender = sender.clone();
+        let call_back = Closure::wrap(Box::new(move |event: web_sys::KeyboardEvent| {
+            let key = event.key();
+            let button = match key.as_str() {
+                "w" => JoypadButton::UP,
+                "s" => JoypadButton::DOWN,
+                "a" => JoypadButton::LEFT,
+                "d" => JoypadButton::RIGHT,
+                "k" => JoypadButton::BUTTON_A,
+                "j" => JoypadButton::BUTTON_B,
+                "Enter" => JoypadButton::START,
+                " " => JoypadButton::SELECT,
+                _ => return,
+            };
+            sender.send((button, pressed)).unwrap();
+        }) as Box<dyn FnMut(_)>);
+
+        document
+            .add_event_listener_with_callback(event_name, call_back.as_ref().unchecked_ref())
+            .unwrap();
+        call_back.forget();
+    }
+}
+
+// identifies the ROM image a .sav belongs to
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
 }
@@ -1 +1,44 @@
-fn main() {}
+use std::{fs::File, io::BufWriter, process};
+
+use rust_nes::nes::Nes;
+
+const SAMPLE_RATE: u32 = 44100;
+
+// headless audio render: rust-nes <rom.nes> <frames> <out.wav>
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 4 {
+        eprintln!("usage: {} <rom.nes> <frames> <out.wav>", args[0]);
+        process::exit(2);
+    }
+    let frames: u32 = args[2].parse().unwrap_or_else(|_| {
+        eprintln!("invalid frame count: {}", args[2]);
+        process::exit(2);
+    });
+
+    let data = std::fs::read(&args[1]).unwrap_or_else(|err| {
+        eprintln!("cannot read {}: {}", args[1], err);
+        process::exit(1);
+    });
+    let mut nes = Nes::new(data).unwrap_or_else(|err| {
+        eprintln!("cannot load {}: {}", args[1], err);
+        process::exit(1);
+    });
+
+    let output = File::create(&args[3]).unwrap_or_else(|err| {
+        eprintln!("cannot create {}: {}", args[3], err);
+        process::exit(1);
+    });
+    let result = nes
+        .start_recording(Box::new(BufWriter::new(output)), SAMPLE_RATE)
+        .and_then(|()| {
+            for _ in 0..frames {
+                nes.run_frame();
+            }
+            nes.stop_recording()
+        });
+    if let Err(err) = result {
+        eprintln!("cannot write {}: {}", args[3], err);
+        process::exit(1);
+    }
+}
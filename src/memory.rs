@@ -1,25 +1,21 @@
+use std::{cell::RefCell, rc::Rc};
+
 use serde::{Deserialize, Serialize};
 use serde_big_array::BigArray;
 
-use crate::{bus::Bus, ROM::ROM};
+use crate::{
+    bus::Bus,
+    ROM::{Mirroring, ROM},
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct CpuMemory {
-    #[serde(with = "BigArray")]
-    pub ram: [u8; 2048],
-    #[serde(skip)]
-    pub rom: Option<ROM>,
-    #[serde(skip)]
-    pub bus: Option<Bus>,
+    pub bus: Bus,
 }
 
 impl CpuMemory {
     pub fn new() -> Self {
-        CpuMemory {
-            ram: [0; 2048],
-            rom: None,
-            bus: None,
-        }
+        CpuMemory { bus: Bus::new() }
     }
 }
 
@@ -31,31 +27,7 @@ impl Default for CpuMemory {
 
 impl CpuMemory {
     pub fn storeb(&mut self, address: u16, data: u8) {
-        match address {
-            0x0000..=0x1FFF => {
-                self.ram[(address & 0x07FF) as usize] = data;
-            }
-            0x2000..=0x3FFF => match address {
-                0x2000 | 0x2006 | 0x2007 => self
-                    .bus
-                    .as_ref()
-                    .expect("cpu has no bus!")
-                    .send_data(address, data),
-                _ => {
-                    let mirror_down_addr = address & 0b0010_0000_0000_0111;
-                    self.storeb(mirror_down_addr, data)
-                }
-            },
-            0x4000..=0x7FFF => {
-                todo!("RAM PPU on ROM not impl!")
-            }
-            0x8000..=0xFFFF => {
-                self.rom
-                    .as_mut()
-                    .expect("not load rom!")
-                    .write(address, data);
-            }
-        }
+        self.bus.write(address, data);
     }
 
     pub fn storew(&mut self, address: u16, data: u16) {
@@ -63,36 +35,13 @@ impl CpuMemory {
         self.storeb(address + 1, ((data >> 8) & 0xFF) as u8)
     }
 
-    pub fn loadb(&self, address: &mut u16) -> u8 {
-        let res = match address {
-            0x0000..=0x1FFF => self.ram[(*address & 0x07FF) as usize],
-            0x2000..=0x3FFF => match address {
-                0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 | 0x4014 => {
-                    panic!("Attempt to read from write-only PPU address {:x}", address);
-                }
-                0x2002 => {
-                    todo!()
-                }
-                0x2007 => self
-                    .bus
-                    .as_ref()
-                    .expect("cpu has no bus!")
-                    .receive_data(*address),
-                _ => {
-                    let mut mirror_down_addr = *address & 0b0010_0000_0000_0111;
-                    self.loadb(&mut mirror_down_addr)
-                }
-            },
-            0x4000..=0x7FFF => {
-                todo!("RAM PPU on ROM not impl!")
-            }
-            0x8000..=0xFFFF => self.rom.as_ref().expect("not load rom!").read(address),
-        };
+    pub fn loadb(&mut self, address: &mut u16) -> u8 {
+        let res = self.bus.read(*address);
         *address += 1;
         res
     }
 
-    pub fn loadw(&self, address: &mut u16) -> u16 {
+    pub fn loadw(&mut self, address: &mut u16) -> u16 {
         let low = self.loadb(address) as u16;
         let high = (self.loadb(address) as u16) << 8;
         high | low
@@ -101,12 +50,11 @@ impl CpuMemory {
 
 #[derive(Serialize, Deserialize)]
 pub struct PpuMemory {
+    // 2KB of console VRAM, the upper 2KB is only present on four-screen carts
     #[serde(with = "BigArray")]
-    pub ram: [u8; 2048],
-    #[serde(skip)]
-    pub rom: Option<ROM>,
+    pub ram: [u8; 4096],
     #[serde(skip)]
-    pub bus: Option<Bus>,
+    pub rom: Option<Rc<RefCell<ROM>>>,
     palette_table: [u8; 32],
     internal_data_buf: u8,
     #[serde(with = "BigArray")]
@@ -116,9 +64,8 @@ pub struct PpuMemory {
 impl PpuMemory {
     pub fn new() -> Self {
         PpuMemory {
-            ram: [0; 2048],
+            ram: [0; 4096],
             rom: None,
-            bus: None,
             palette_table: [0; 32],
             internal_data_buf: 0,
             oam_data: [0; 64 * 4],
@@ -134,15 +81,16 @@ impl Default for PpuMemory {
 
 impl PpuMemory {
     pub fn storeb(&mut self, address: u16, data: u8) {
-        if address < 0x2000 {
-            self.ram[(address & 0x07FF) as usize] = data;
-        } else if address < 0x6000 {
-            todo!()
-        } else {
-            self.rom
-                .as_mut()
-                .expect("not load rom!")
-                .write(address, data);
+        match address {
+            0..=0x1fff => self
+                .rom
+                .as_ref()
+                .expect("not load chr")
+                .borrow_mut()
+                .ppu_write(address, data),
+            0x2000..=0x3eff => self.ram[self.mirror_vram_addr(address)] = data,
+            0x3f00..=0x3fff => self.palette_table[palette_index(address)] = data & 0x3f,
+            _ => panic!("unexpected access to mirrored space {}", address),
         }
     }
 
@@ -151,24 +99,19 @@ impl PpuMemory {
         self.storeb(address + 1, ((data >> 8) & 0xFF) as u8)
     }
 
+    // read through the PPUDATA buffer, palette reads are returned immediately
+    // while the buffer picks up the nametable byte underneath
     pub fn loadb(&mut self, address: &mut u16) -> u8 {
         match *address {
-            0..=0x1fff => {
+            0..=0x3eff => {
                 let result = self.internal_data_buf;
-                self.internal_data_buf = self.rom.as_ref().expect("not load chr").read(address);
+                self.internal_data_buf = self.fetch(*address);
                 result
             }
-            0x2000..=0x2fff => {
-                let result = self.internal_data_buf;
-                self.internal_data_buf = self.ram[*address as usize];
-                result
+            _ => {
+                self.internal_data_buf = self.fetch(*address - 0x1000);
+                self.fetch(*address)
             }
-            0x3000..=0x3eff => panic!(
-                "addr space 0x3000..0x3eff is not expected to be used, requested = {} ",
-                *address
-            ),
-            0x3f00..=0x3fff => self.palette_table[(*address - 0x3f00) as usize],
-            _ => panic!("unexpected access to mirrored space {}", *address),
         }
     }
 
@@ -177,4 +120,59 @@ impl PpuMemory {
         let high = (self.loadb(address) as u16) << 8;
         high | low
     }
+
+    // read without side effects, used by the renderer
+    pub fn fetch(&self, address: u16) -> u8 {
+        match address {
+            0..=0x1fff => self
+                .rom
+                .as_ref()
+                .expect("not load chr")
+                .borrow_mut()
+                .ppu_read(address),
+            0x2000..=0x3eff => self.ram[self.mirror_vram_addr(address)],
+            0x3f00..=0x3fff => self.palette_table[palette_index(address)],
+            _ => panic!("unexpected access to mirrored space {}", address),
+        }
+    }
+}
+
+impl PpuMemory {
+    // Horizontal:
+    //   [ A ] [ a ]
+    //   [ B ] [ b ]
+
+    // Vertical:
+    //   [ A ] [ B ]
+    //   [ a ] [ b ]
+    fn mirror_vram_addr(&self, addr: u16) -> usize {
+        let mirrored_vram = addr & 0b10111111111111; // mirror down 0x3000-0x3eff to 0x2000 - 0x2eff
+        let vram_index = (mirrored_vram - 0x2000) as usize; // to vram vector
+        let name_table = vram_index / 0x400; // to the name table index
+        let mirroring = self
+            .rom
+            .as_ref()
+            .expect("not load rom!")
+            .borrow()
+            .mirroring();
+        let bank = match (mirroring, name_table) {
+            (Mirroring::VERTICAL, n) => n & 1,
+            (Mirroring::HORIZONTAL, n) => n >> 1,
+            (Mirroring::FOUR_SCREEN, n) => n,
+            (Mirroring::SINGLE_SCREEN_LOWER_BANK, _) => 0,
+            (Mirroring::SINGLE_SCREEN_UPPER_BANK, _) => 1,
+        };
+        bank * 0x400 + vram_index % 0x400
+    }
+}
+
+// $3F20-$3FFF mirror $3F00-$3F1F, and the transparent entries of the sprite
+// palettes $3F10/$3F14/$3F18/$3F1C mirror $3F00/$3F04/$3F08/$3F0C
+fn palette_index(address: u16) -> usize {
+    let index = (address & 0x1f) as usize;
+    if index & 0b10011 == 0b10000 {
+        index - 0x10
+    } else {
+        index
+    }
 }
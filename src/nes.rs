@@ -0,0 +1,239 @@
+use std::{cell::RefCell, io, rc::Rc};
+
+use log::warn;
+
+use crate::{
+    apu_impl::mixer::Channel,
+    audio::Resampler,
+    clock::{Clock, Region},
+    cpu::CPU,
+    joypad::JoypadButton,
+    ppu_impl::ppu::{Frame, PPU},
+    wav::{WavRecorder, WavWriter},
+    ROM::{RomError, ROM},
+};
+
+#[derive(Default)]
+struct Events {
+    instruction: bool,
+    vblank: bool,
+}
+
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+pub struct Nes {
+    cpu: CPU,
+    clock: Clock,
+    audio: Resampler,
+    recorder: Option<WavRecorder>,
+}
+
+impl Nes {
+    pub fn new(data: Vec<u8>) -> Result<Self, RomError> {
+        let mut cpu = CPU::new();
+        cpu.load_rom(data)?;
+        cpu.reset();
+        let region = cpu
+            .mem
+            .bus
+            .rom
+            .as_ref()
+            .expect("not load rom!")
+            .borrow()
+            .region();
+        let mut nes = Nes {
+            cpu,
+            clock: Clock::new(region),
+            audio: Resampler::new(cpu_clock_rate(region), DEFAULT_SAMPLE_RATE),
+            recorder: None,
+        };
+        nes.set_region(region);
+        Ok(nes)
+    }
+
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    pub fn region(&self) -> Region {
+        self.clock.region()
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.clock.set_region(region);
+        self.ppu().set_region(region);
+        self.cpu.mem.bus.apu.set_region(region);
+        self.audio.set_clock_rate(cpu_clock_rate(region));
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.set_clock_rate(cpu_clock_rate(region));
+        }
+    }
+
+    pub fn frame(&self) -> &Frame {
+        &self.cpu.mem.bus.ppu.frame
+    }
+
+    // sprite memory, 64 entries of Y, tile, attributes and X
+    pub fn oam(&self) -> &[u8] {
+        &self.cpu.mem.bus.ppu.mem.oam_data
+    }
+
+    pub fn master_cycles(&self) -> u64 {
+        self.clock.master_cycles()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.audio.sample_rate()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.audio.set_sample_rate(sample_rate);
+    }
+
+    // nudge the audio rate by up to ±0.5% to keep the host's audio queue from
+    // draining or overflowing when video is paced by the display
+    pub fn set_audio_rate_adjust(&mut self, ratio: f64) {
+        self.audio.set_rate_adjust(ratio);
+    }
+
+    pub fn samples_available(&self) -> usize {
+        self.audio.samples_available()
+    }
+
+    // pull resampled audio, return the number of samples written
+    pub fn read_samples(&mut self, out: &mut [f32]) -> usize {
+        self.audio.read_samples(out)
+    }
+
+    pub fn read_samples_i16(&mut self, out: &mut [i16]) -> usize {
+        self.audio.read_samples_i16(out)
+    }
+
+    pub fn set_channel_volume(&mut self, channel: Channel, volume: f32) {
+        self.cpu.mem.bus.apu.set_channel_volume(channel, volume);
+    }
+
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.cpu.mem.bus.apu.set_channel_muted(channel, muted);
+    }
+
+    pub fn set_channel_solo(&mut self, channel: Channel, solo: bool) {
+        self.cpu.mem.bus.apu.set_channel_solo(channel, solo);
+    }
+
+    // capture the audio produced from now on as a WAV file, independently of read_samples
+    pub fn start_recording(
+        &mut self,
+        writer: Box<dyn WavWriter>,
+        sample_rate: u32,
+    ) -> io::Result<()> {
+        self.stop_recording()?;
+        let clock_rate = cpu_clock_rate(self.region());
+        self.recorder = Some(WavRecorder::new(writer, clock_rate, sample_rate)?);
+        Ok(())
+    }
+
+    // finalise the WAV file, does nothing when not recording
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // update a button of the controller plugged into port 1 or 2, other ports
+    // are ignored so a bad call from the front end can't abort the page
+    pub fn set_button(&mut self, player: u8, button: JoypadButton, pressed: bool) {
+        let joypad = match player {
+            1 => &mut self.cpu.mem.bus.joypad1,
+            2 => &mut self.cpu.mem.bus.joypad2,
+            _ => {
+                warn!("there is no controller port {}", player);
+                return;
+            }
+        };
+        joypad.set_button_pressed_status(button, pressed);
+    }
+
+    // PRG-RAM contents to write to a .sav file, None unless the cart has a battery
+    pub fn battery_ram(&self) -> Option<Vec<u8>> {
+        self.rom().borrow().battery_ram().map(|ram| ram.to_vec())
+    }
+
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        self.rom().borrow_mut().load_battery_ram(data);
+    }
+
+    // snapshot of the console, including the cartridge's RAM and mapper registers
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let cpu: serde_json::Value =
+            serde_json::from_slice(&self.cpu.save()).expect("encode state failed!");
+        serde_json::to_vec(&(&self.clock, cpu)).expect("encode state failed!")
+    }
+
+    // a corrupt state or one saved with another mapper leaves the console as it was
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), serde_json::Error> {
+        let (clock, cpu): (Clock, serde_json::Value) = serde_json::from_slice(data)?;
+        self.cpu.load(&serde_json::to_vec(&cpu)?)?;
+        self.clock = clock;
+        Ok(())
+    }
+
+    fn rom(&self) -> &Rc<RefCell<ROM>> {
+        self.cpu.mem.bus.rom.as_ref().expect("not load rom!")
+    }
+
+    fn ppu(&mut self) -> &mut PPU {
+        &mut self.cpu.mem.bus.ppu
+    }
+}
+
+impl Nes {
+    // clock until the CPU begins the next instruction, return master cycles elapsed
+    pub fn step_instruction(&mut self) -> u64 {
+        let start = self.clock.master_cycles();
+        while let Some(events) = self.tick(u64::MAX) {
+            if events.instruction {
+                break;
+            }
+        }
+        self.clock.master_cycles() - start
+    }
+
+    // run the given number of master clock cycles
+    pub fn run_cycles(&mut self, cycles: u64) {
+        let target = self.clock.master_cycles() + cycles;
+        while self.tick(target).is_some() {}
+    }
+
+    // run until the next vertical blank, when the frame has been fully drawn
+    pub fn run_frame(&mut self) {
+        while let Some(events) = self.tick(u64::MAX) {
+            if events.vblank {
+                break;
+            }
+        }
+    }
+
+    fn tick(&mut self, target: u64) -> Option<Events> {
+        let tick = self.clock.advance(target)?;
+        let mut events = Events::default();
+        if tick.cpu {
+            events.instruction = self.cpu.clock();
+            self.cpu.mem.bus.clock();
+            let amplitude = self.cpu.mem.bus.apu.output();
+            self.audio.tick(amplitude);
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.tick(amplitude);
+            }
+        }
+        if tick.ppu {
+            events.vblank = self.ppu().tick();
+        }
+        Some(events)
+    }
+}
+
+fn cpu_clock_rate(region: Region) -> f64 {
+    region.master_clock_rate() as f64 / region.cpu_divider() as f64
+}
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+
+// Internal VRAM address shared by PPUSCROLL, PPUADDR and the renderer
+//
+// yyy NN YYYYY XXXXX
+// ||| || ||||| +++++-- coarse X scroll
+// ||| || +++++-------- coarse Y scroll
+// ||| ++-------------- nametable select
+// +++----------------- fine Y scroll
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct LoopyRegister {
+    value: u16,
+}
+
+impl LoopyRegister {
+    pub fn new() -> Self {
+        LoopyRegister { value: 0 }
+    }
+
+    pub fn get(&self) -> u16 {
+        self.value
+    }
+
+    pub fn set(&mut self, data: u16) {
+        self.value = data & 0x7fff;
+    }
+
+    pub fn coarse_x(&self) -> u16 {
+        self.value & 0b11111
+    }
+
+    pub fn set_coarse_x(&mut self, data: u8) {
+        self.value = (self.value & !0b11111) | (data as u16 & 0b11111);
+    }
+
+    pub fn coarse_y(&self) -> u16 {
+        (self.value >> 5) & 0b11111
+    }
+
+    pub fn set_coarse_y(&mut self, data: u8) {
+        self.value = (self.value & !(0b11111 << 5)) | ((data as u16 & 0b11111) << 5);
+    }
+
+    pub fn set_nametable(&mut self, data: u8) {
+        self.value = (self.value & !(0b11 << 10)) | ((data as u16 & 0b11) << 10);
+    }
+
+    pub fn fine_y(&self) -> u16 {
+        (self.value >> 12) & 0b111
+    }
+
+    pub fn set_fine_y(&mut self, data: u8) {
+        self.value = (self.value & !(0b111 << 12)) | ((data as u16 & 0b111) << 12);
+    }
+
+    // address of the tile byte in the nametables
+    pub fn tile_addr(&self) -> u16 {
+        0x2000 | (self.value & 0x0fff)
+    }
+
+    // address of the attribute byte covering the current tile
+    pub fn attr_addr(&self) -> u16 {
+        0x23c0 | (self.value & 0x0c00) | ((self.value >> 4) & 0x38) | ((self.value >> 2) & 0x07)
+    }
+
+    // increment after a PPUDATA access outside of rendering
+    pub fn increment(&mut self, inc: u8) {
+        self.value = self.value.wrapping_add(inc as u16) & 0x7fff;
+    }
+
+    // move to the next tile, switching horizontal nametable past column 31
+    pub fn increment_x(&mut self) {
+        if self.coarse_x() == 31 {
+            self.value &= !0b11111;
+            self.value ^= 0x0400;
+        } else {
+            self.value += 1;
+        }
+    }
+
+    // move to the next pixel row, switching vertical nametable past row 29
+    pub fn increment_y(&mut self) {
+        if self.fine_y() < 7 {
+            self.value += 0x1000;
+            return;
+        }
+        self.value &= !0x7000;
+        match self.coarse_y() {
+            29 => {
+                self.set_coarse_y(0);
+                self.value ^= 0x0800;
+            }
+            // rows 30 and 31 hold attributes and wrap without switching nametable
+            31 => self.set_coarse_y(0),
+            y => self.set_coarse_y(y as u8 + 1),
+        }
+    }
+
+    // coarse X and the horizontal nametable bit
+    pub fn copy_horizontal(&mut self, other: &LoopyRegister) {
+        let mask = 0x041f;
+        self.value = (self.value & !mask) | (other.value & mask);
+    }
+
+    // fine Y, coarse Y and the vertical nametable bit
+    pub fn copy_vertical(&mut self, other: &LoopyRegister) {
+        let mask = 0x7be0;
+        self.value = (self.value & !mask) | (other.value & mask);
+    }
+}
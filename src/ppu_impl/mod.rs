@@ -1,3 +1,6 @@
-mod address;
 mod control;
+mod loopy;
+mod mask;
 pub mod ppu;
+mod sprite;
+mod status;
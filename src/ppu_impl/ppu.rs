@@ -1,13 +1,18 @@
-use crate::consts::{SYSTEM_PALLETE, WIDTH};
-use crate::{bus::Bus, memory::PpuMemory, ROM::ROM};
-use serde::{Deserialize, Serialize};
-use std::{
-    sync::mpsc::{Receiver, Sender},
-    thread,
+use std::{cell::RefCell, rc::Rc};
+
+use crate::consts::{HEIGHT, SYSTEM_PALLETE, WIDTH};
+use crate::{
+    clock::Region,
+    memory::PpuMemory,
+    ROM::{RomError, ROM},
 };
+use serde::{Deserialize, Serialize};
 
-use super::address::AddrRegister;
 use super::control::ControlRegister;
+use super::loopy::LoopyRegister;
+use super::mask::MaskRegister;
+use super::sprite::{Sprite, SpriteAttributes};
+use super::status::StatusRegister;
 
 pub struct Frame {
     pub data: Vec<u8>,
@@ -29,11 +34,58 @@ impl Frame {
     }
 }
 
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new(WIDTH, HEIGHT)
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct PPU {
     pub mem: PpuMemory,
-    addr: AddrRegister,
     ctrl: ControlRegister,
+    mask: MaskRegister,
+    status: StatusRegister,
+    oam_addr: u8,
+    // current VRAM address, temporary VRAM address, fine X scroll and the
+    // first/second write toggle shared by $2005 and $2006
+    v: LoopyRegister,
+    t: LoopyRegister,
+    fine_x: u8,
+    write_latch: bool,
+    // last value driven on the CPU-PPU data bus, returned by reads of write-only registers
+    data_bus: u8,
+    // NMI output is vblank AND GENERATE_NMI, the CPU latches its rising edge
+    nmi_line: bool,
+    nmi_pending: bool,
+    // set when $2002 is read the dot before vblank starts
+    suppress_vblank: bool,
+
+    region: Region,
+    scanline: u16,
+    dot: u16,
+    frame_count: u64,
+    // dots since power on, lets the cartridge time PPU bus activity
+    cycle: u64,
+
+    // latches filled by the nametable, attribute and pattern fetches
+    next_tile_id: u8,
+    next_tile_attr: u8,
+    next_tile_lo: u8,
+    next_tile_hi: u8,
+    // the current tile lives in the high byte, the next one in the low byte
+    bg_pattern_lo: u16,
+    bg_pattern_hi: u16,
+    bg_attr_lo: u16,
+    bg_attr_hi: u16,
+
+    // sprites found by evaluation for the scanline being drawn
+    sprites: [Sprite; 8],
+    sprite_count: usize,
+    sprite_zero_on_line: bool,
+
+    #[serde(skip)]
+    pub frame: Frame,
 }
 
 impl Default for PPU {
@@ -46,8 +98,35 @@ impl PPU {
     pub fn new() -> Self {
         PPU {
             mem: PpuMemory::new(),
-            addr: AddrRegister::new(),
             ctrl: ControlRegister::new(),
+            mask: MaskRegister::new(),
+            status: StatusRegister::new(),
+            oam_addr: 0,
+            v: LoopyRegister::new(),
+            t: LoopyRegister::new(),
+            fine_x: 0,
+            write_latch: false,
+            data_bus: 0,
+            nmi_line: false,
+            nmi_pending: false,
+            suppress_vblank: false,
+            region: Region::default(),
+            scanline: 0,
+            dot: 0,
+            frame_count: 0,
+            cycle: 0,
+            next_tile_id: 0,
+            next_tile_attr: 0,
+            next_tile_lo: 0,
+            next_tile_hi: 0,
+            bg_pattern_lo: 0,
+            bg_pattern_hi: 0,
+            bg_attr_lo: 0,
+            bg_attr_hi: 0,
+            sprites: [Sprite::new(); 8],
+            sprite_count: 0,
+            sprite_zero_on_line: false,
+            frame: Frame::default(),
         }
     }
 
@@ -61,114 +140,469 @@ impl PPU {
         serde_json::to_vec(self).unwrap()
     }
 
-    pub fn load_rom(&mut self, data: Vec<u8>) {
-        self.mem.rom = Some(ROM::new(data, "ppu"));
+    pub fn load_rom(&mut self, data: Vec<u8>) -> Result<(), RomError> {
+        self.set_cartridge(Rc::new(RefCell::new(ROM::new(data)?)));
+        Ok(())
+    }
+
+    pub fn set_cartridge(&mut self, rom: Rc<RefCell<ROM>>) {
+        self.mem.rom = Some(rom);
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
     }
 
-    pub fn load_bus(&mut self, sender: Sender<(u16, u8)>, receiver: Receiver<(u16, u8)>) {
-        self.mem.bus = Some(Bus::new(sender, receiver));
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
     }
 }
 
 impl PPU {
-    pub fn run(&mut self, frame: &mut Frame) {
-        loop {
-            self.render(frame);
-            println!("run ppu!");
-            thread::sleep(std::time::Duration::from_secs(1));
+    // advance one dot, return true when vertical blank begins
+    pub fn tick(&mut self) -> bool {
+        let vblank = self.scanline == self.region.vblank_scanline() && self.dot == 1;
+        if vblank {
+            if !self.suppress_vblank {
+                self.status.set_vblank_status(true);
+                self.update_nmi();
+            }
+            self.suppress_vblank = false;
+        }
+        if self.scanline == self.pre_render_scanline() && self.dot == 1 {
+            self.status.set_vblank_status(false);
+            self.status.set_sprite_zero_hit(false);
+            self.status.set_sprite_overflow(false);
+            self.update_nmi();
+        }
+
+        if self.scanline < 240 || self.scanline == self.pre_render_scanline() {
+            self.render_dot();
+        }
+        self.next_dot();
+        vblank
+    }
+
+    // return true once for every NMI the PPU raised
+    pub fn poll_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+
+    fn update_nmi(&mut self) {
+        let line = self.status.is_in_vblank() && self.ctrl.generate_nmi();
+        if line && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = line;
+    }
+
+    fn pre_render_scanline(&self) -> u16 {
+        self.region.scanlines() - 1
+    }
+
+    fn next_dot(&mut self) {
+        // the NTSC PPU skips the last dot of the pre-render scanline on odd frames
+        if self.region == Region::NTSC
+            && self.mask.rendering_enabled()
+            && self.scanline == self.pre_render_scanline()
+            && self.dot == 339
+            && self.frame_count % 2 == 1
+        {
+            self.dot = 340;
+        }
+
+        self.cycle += 1;
+        self.dot += 1;
+        if self.dot > 340 {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline >= self.region.scanlines() {
+                self.scanline = 0;
+                self.frame_count += 1;
+            }
         }
     }
 }
 
 impl PPU {
-    fn write_to_ppu_addr(&mut self) {
-        let data = self.get_data(0x2006);
-        self.addr.update(data);
+    //  dot  0         idle
+    //  dot  1 - 256   draw pixels, fetch the tiles of this scanline
+    //  dot 257 - 320  sprite fetches
+    //  dot 321 - 336  fetch the first two tiles of the next scanline
+    //  dot 337 - 340  unused nametable fetches
+    fn render_dot(&mut self) {
+        let dot = self.dot;
+
+        if self.mask.rendering_enabled() {
+            self.fetch_background(dot);
+            if (257..=320).contains(&dot) {
+                self.oam_addr = 0;
+            }
+            if dot == 257 {
+                self.evaluate_sprites();
+            }
+        }
+
+        if (1..=256).contains(&dot) && self.scanline < 240 {
+            self.draw_pixel();
+        }
     }
 
-    fn write_to_ctrl(&mut self) {
-        let data = self.get_data(0x2000);
-        self.ctrl.update(data);
+    fn fetch_background(&mut self, dot: u16) {
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.shift_background();
+        }
+
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background();
+                    self.fetch_tile_id();
+                }
+                2 => self.fetch_tile_attr(),
+                4 => self.fetch_tile_pattern(0),
+                6 => self.fetch_tile_pattern(8),
+                7 => self.v.increment_x(),
+                _ => {}
+            }
+        }
+
+        match dot {
+            256 => self.v.increment_y(),
+            257 => self.v.copy_horizontal(&self.t),
+            280..=304 if self.scanline == self.pre_render_scanline() => {
+                self.v.copy_vertical(&self.t)
+            }
+            _ => {}
+        }
     }
 
-    fn write_data(&mut self) {
-        let addr = self.addr.get();
-        let data = self.get_data(0x2007);
-        self.mem.storeb(addr, data);
-        self.increment_vram_addr();
+    fn fetch_tile_id(&mut self) {
+        self.next_tile_id = self.read_vram(self.v.tile_addr());
     }
 
-    fn increment_vram_addr(&mut self) {
-        self.addr.increment(self.ctrl.vram_addr_increment());
+    // each attribute byte covers a 32x32 pixel area split into four 16x16 quadrants
+    fn fetch_tile_attr(&mut self) {
+        let shift = ((self.v.coarse_y() & 0b10) << 1) | (self.v.coarse_x() & 0b10);
+        self.next_tile_attr = (self.read_vram(self.v.attr_addr()) >> shift) & 0b11;
     }
 
-    fn read_data(&mut self) -> u8 {
-        let mut addr = self.addr.get();
-        self.increment_vram_addr();
-        self.mem.loadb(&mut addr)
+    fn fetch_tile_pattern(&mut self, plane: u16) {
+        let addr = self.ctrl.background_pattern_addr()
+            + (self.next_tile_id as u16) * 16
+            + plane
+            + self.v.fine_y();
+        let data = self.read_vram(addr);
+        if plane == 0 {
+            self.next_tile_lo = data;
+        } else {
+            self.next_tile_hi = data;
+        }
     }
 
-    fn get_data(&self, expect_addr: u16) -> u8 {
-        self.mem
-            .bus
-            .as_ref()
-            .expect("not load bus")
-            .receive_data(expect_addr)
+    // rendering fetch, the cartridge watches the address bus (MMC3 counts A12 rises)
+    fn read_vram(&mut self, addr: u16) -> u8 {
+        self.notify_address(addr);
+        self.mem.fetch(addr)
+    }
+
+    fn notify_address(&mut self, addr: u16) {
+        if let Some(rom) = self.mem.rom.as_ref() {
+            rom.borrow_mut().ppu_address(addr, self.cycle);
+        }
     }
-}
 
-// impl PPU {
-//     // Horizontal:
-//     //   [ A ] [ a ]
-//     //   [ B ] [ b ]
-
-//     // Vertical:
-//     //   [ A ] [ B ]
-//     //   [ a ] [ b ]
-//     pub fn mirror_vram_addr(&self, addr: u16) -> u16 {
-//         let mirrored_vram = addr & 0b10111111111111; // mirror down 0x3000-0x3eff to 0x2000 - 0x2eff
-//         let vram_index = mirrored_vram - 0x2000; // to vram vector
-//         let name_table = vram_index / 0x400; // to the name table index
-//         match (&self.mirroring, name_table) {
-//             (Mirroring::VERTICAL, 2) | (Mirroring::VERTICAL, 3) => vram_index - 0x800,
-//             (Mirroring::HORIZONTAL, 2) => vram_index - 0x400,
-//             (Mirroring::HORIZONTAL, 1) => vram_index - 0x400,
-//             (Mirroring::HORIZONTAL, 3) => vram_index - 0x800,
-//             _ => vram_index,
-//         }
-//     }
-// }
+    fn load_background(&mut self) {
+        self.bg_pattern_lo = (self.bg_pattern_lo & 0xff00) | self.next_tile_lo as u16;
+        self.bg_pattern_hi = (self.bg_pattern_hi & 0xff00) | self.next_tile_hi as u16;
+        let attr_lo = if self.next_tile_attr & 0b01 != 0 {
+            0xff
+        } else {
+            0
+        };
+        let attr_hi = if self.next_tile_attr & 0b10 != 0 {
+            0xff
+        } else {
+            0
+        };
+        self.bg_attr_lo = (self.bg_attr_lo & 0xff00) | attr_lo;
+        self.bg_attr_hi = (self.bg_attr_hi & 0xff00) | attr_hi;
+    }
+
+    fn shift_background(&mut self) {
+        self.bg_pattern_lo <<= 1;
+        self.bg_pattern_hi <<= 1;
+        self.bg_attr_lo <<= 1;
+        self.bg_attr_hi <<= 1;
+    }
+
+    fn draw_pixel(&mut self) {
+        let x = self.dot - 1;
+
+        let mut bg_pixel = 0;
+        let mut bg_palette = 0;
+        if self.mask.show_background() && (x >= 8 || self.mask.show_leftmost_background()) {
+            let bit = 0x8000 >> self.fine_x;
+            bg_pixel = ((self.bg_pattern_hi & bit != 0) as u16) << 1
+                | (self.bg_pattern_lo & bit != 0) as u16;
+            bg_palette =
+                ((self.bg_attr_hi & bit != 0) as u16) << 1 | (self.bg_attr_lo & bit != 0) as u16;
+        }
+
+        let mut sprite_pixel = 0;
+        let mut sprite_palette = 0;
+        let mut behind_background = false;
+        if self.mask.show_sprites() && (x >= 8 || self.mask.show_leftmost_sprites()) {
+            // the first opaque sprite in OAM order wins
+            for (i, sprite) in self.sprites[..self.sprite_count].iter().enumerate() {
+                let pixel = sprite.pixel(x) as u16;
+                if pixel == 0 {
+                    continue;
+                }
+                if i == 0 && self.sprite_zero_on_line && bg_pixel != 0 && x != 255 {
+                    self.status.set_sprite_zero_hit(true);
+                }
+                sprite_pixel = pixel;
+                sprite_palette = sprite.attr.palette() as u16;
+                behind_background = sprite.attr.behind_background();
+                break;
+            }
+        }
+
+        let addr = match (bg_pixel, sprite_pixel) {
+            // with rendering off the backdrop comes from the palette entry v points at
+            (0, 0) if !self.mask.rendering_enabled() && self.v.get() & 0x3f00 == 0x3f00 => {
+                self.v.get() & 0x3fff
+            }
+            (0, 0) => 0x3f00,
+            (0, _) => 0x3f10 + (sprite_palette << 2 | sprite_pixel),
+            (_, 0) => 0x3f00 + (bg_palette << 2 | bg_pixel),
+            _ if behind_background => 0x3f00 + (bg_palette << 2 | bg_pixel),
+            _ => 0x3f10 + (sprite_palette << 2 | sprite_pixel),
+        };
+        let color = self.mem.fetch(addr);
+        let rgb = self.output_color(color);
+        self.frame
+            .set_pixel(x as usize, self.scanline as usize, rgb);
+    }
+
+    fn output_color(&self, color: u8) -> (u8, u8, u8) {
+        let color = if self.mask.is_greyscale() {
+            color & 0x30
+        } else {
+            color & 0x3f
+        };
+        let (r, g, b) = SYSTEM_PALLETE[color as usize];
+
+        // PAL and Dendy PPUs swap the red and green emphasis bits
+        let (mut red, mut green, blue) = self.mask.emphasis();
+        if self.region != Region::NTSC {
+            std::mem::swap(&mut red, &mut green);
+        }
+        if !(red || green || blue) {
+            return (r, g, b);
+        }
+        let dim = |channel: u8, emphasised: bool| {
+            if emphasised {
+                channel
+            } else {
+                (channel as u16 * 3 / 4) as u8
+            }
+        };
+        (dim(r, red), dim(g, green), dim(b, blue))
+    }
+}
 
 impl PPU {
-    pub fn render(&mut self, frame: &mut Frame) {
-        let bank = 0;
-
-        for i in 0..0x03c0 {
-            let mut addr = i as u16;
-            let tile = self.mem.loadb(&mut addr);
-            let tile_x = i % 32;
-            let tile_y = i / 32;
-            let tile = &self.mem.rom.as_ref().unwrap().chr.as_ref().unwrap()
-                [(bank + tile * 16) as usize..=(bank + tile * 16 + 15) as usize];
-
-            for y in 0..=7 {
-                let mut upper = tile[y];
-                let mut lower = tile[y + 8];
-
-                for x in (0..=7).rev() {
-                    let value = (1 & upper) << 1 | (1 & lower);
-                    upper >>= 1;
-                    lower >>= 1;
-                    let rgb = match value {
-                        0 => SYSTEM_PALLETE[0x01],
-                        1 => SYSTEM_PALLETE[0x23],
-                        2 => SYSTEM_PALLETE[0x27],
-                        3 => SYSTEM_PALLETE[0x30],
-                        _ => panic!("can't be"),
-                    };
-                    frame.set_pixel(tile_x * 8 + x, tile_y * 8 + y, rgb)
+    // Find the sprites of the next scanline and fetch their patterns. Real hardware
+    // spreads this over dots 65 - 320, here it happens in one go at dot 257.
+    fn evaluate_sprites(&mut self) {
+        let height = self.ctrl.sprite_size();
+        let in_range = |y: u8| self.scanline.wrapping_sub(y as u16) < height;
+
+        let mut found = [0usize; 8];
+        let mut count = 0;
+        let mut sprite_zero = false;
+        if self.scanline != self.pre_render_scanline() {
+            let mut n = 0;
+            let mut m = 0;
+            while n < 64 {
+                if count < 8 {
+                    if in_range(self.mem.oam_data[n * 4]) {
+                        sprite_zero |= n == 0;
+                        found[count] = n;
+                        count += 1;
+                    }
+                } else {
+                    // after eight sprites the PPU wrongly steps the byte offset along
+                    // with the sprite index, which makes the overflow flag unreliable
+                    if in_range(self.mem.oam_data[n * 4 + m]) {
+                        self.status.set_sprite_overflow(true);
+                        break;
+                    }
+                    m = (m + 1) & 0b11;
                 }
+                n += 1;
+            }
+        }
+
+        // all eight slots are fetched, unused ones with tile $FF
+        for (slot, &n) in found.iter().enumerate() {
+            let mut sprite = Sprite::new();
+            let (y, tile) = if slot < count {
+                sprite.x = self.mem.oam_data[n * 4 + 3];
+                sprite.attr = SpriteAttributes::from_bits_truncate(self.mem.oam_data[n * 4 + 2]);
+                (self.mem.oam_data[n * 4], self.mem.oam_data[n * 4 + 1])
+            } else {
+                (0xff, 0xff)
+            };
+
+            let mut row = self.scanline.wrapping_sub(y as u16) % height;
+            if sprite.attr.flip_vertically() {
+                row = height - 1 - row;
             }
+            let addr = if height == 16 {
+                // 8x16 sprites take the pattern table from bit 0 of the tile index
+                let table = (tile & 1) as u16 * 0x1000;
+                let tile = (tile & 0xfe) as u16 + row / 8;
+                table + tile * 16 + row % 8
+            } else {
+                self.ctrl.sprite_pattern_addr() + tile as u16 * 16 + row
+            };
+            sprite.pattern_lo = self.read_vram(addr);
+            sprite.pattern_hi = self.read_vram(addr + 8);
+            if sprite.attr.flip_horizontally() {
+                sprite.pattern_lo = sprite.pattern_lo.reverse_bits();
+                sprite.pattern_hi = sprite.pattern_hi.reverse_bits();
+            }
+            self.sprites[slot] = sprite;
+        }
+        self.sprite_count = count;
+        self.sprite_zero_on_line = sprite_zero;
+    }
+}
+
+impl PPU {
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        self.data_bus = data;
+        match address {
+            0x2000 => self.write_to_ctrl(data),
+            0x2001 => self.mask.update(data),
+            0x2003 => self.oam_addr = data,
+            0x2004 => self.write_oam_data(data),
+            0x2005 => self.write_to_scroll(data),
+            0x2006 => self.write_to_ppu_addr(data),
+            0x2007 => self.write_data(data),
+            _ => panic!("unexpected ppu register write {:x}", address),
         }
     }
+
+    pub fn read_register(&mut self, address: u16) -> u8 {
+        let data = match address {
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 => self.data_bus,
+            0x2002 => self.read_status(),
+            0x2004 => self.read_oam_data(),
+            0x2007 => self.read_data(),
+            _ => panic!("unexpected ppu register read {:x}", address),
+        };
+        self.data_bus = data;
+        data
+    }
+
+    // reading the status clears the vblank flag and the shared $2005/$2006 write latch
+    fn read_status(&mut self) -> u8 {
+        if self.scanline == self.region.vblank_scanline() {
+            match self.dot {
+                // one dot early: the flag reads clear and is never set this frame
+                1 => self.suppress_vblank = true,
+                // on the same or the next dot: the flag reads set but no NMI happens
+                2 | 3 => self.nmi_pending = false,
+                _ => {}
+            }
+        }
+        let data = (self.status.snapshot() & 0b1110_0000) | (self.data_bus & 0b0001_1111);
+        self.status.set_vblank_status(false);
+        self.update_nmi();
+        self.write_latch = false;
+        data
+    }
+
+    fn write_oam_data(&mut self, data: u8) {
+        self.mem.oam_data[self.oam_addr as usize] = data;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    fn read_oam_data(&self) -> u8 {
+        let data = self.mem.oam_data[self.oam_addr as usize];
+        // bits 2-4 of the sprite attribute byte do not exist
+        if self.oam_addr & 0b11 == 2 {
+            data & 0b1110_0011
+        } else {
+            data
+        }
+    }
+
+    fn write_to_scroll(&mut self, data: u8) {
+        if !self.write_latch {
+            self.t.set_coarse_x(data >> 3);
+            self.fine_x = data & 0b111;
+        } else {
+            self.t.set_fine_y(data & 0b111);
+            self.t.set_coarse_y(data >> 3);
+        }
+        self.write_latch = !self.write_latch;
+    }
+
+    fn write_to_ppu_addr(&mut self, data: u8) {
+        if !self.write_latch {
+            self.t
+                .set(((data as u16 & 0x3f) << 8) | (self.t.get() & 0x00ff));
+        } else {
+            self.t.set((self.t.get() & 0xff00) | data as u16);
+            self.v = self.t;
+            self.notify_address(self.v.get());
+        }
+        self.write_latch = !self.write_latch;
+    }
+
+    // enabling NMI while the vblank flag is set raises an NMI straight away
+    fn write_to_ctrl(&mut self, data: u8) {
+        self.ctrl.update(data);
+        self.t.set_nametable(data);
+        self.update_nmi();
+    }
+
+    fn write_data(&mut self, data: u8) {
+        let addr = self.v.get() & 0x3fff;
+        self.notify_address(addr);
+        self.mem.storeb(addr, data);
+        self.increment_vram_addr();
+    }
+
+    // during rendering a PPUDATA access bumps coarse X and Y instead
+    fn increment_vram_addr(&mut self) {
+        let rendering = self.mask.rendering_enabled()
+            && (self.scanline < 240 || self.scanline == self.pre_render_scanline());
+        if rendering {
+            self.v.increment_x();
+            self.v.increment_y();
+        } else {
+            self.v.increment(self.ctrl.vram_addr_increment());
+        }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        let mut addr = self.v.get() & 0x3fff;
+        self.notify_address(addr);
+        self.increment_vram_addr();
+        self.mem.loadb(&mut addr)
+    }
 }
@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+
+bitflags::bitflags! {
+
+   // 7  bit  0
+   // ---- ----
+   // VHP. ..PP
+   // |||| ||||
+   // |||| ||++- Palette (4 to 7) of sprite
+   // |||+-++--- Unimplemented (read 0)
+   // ||+------- Priority (0: in front of background; 1: behind background)
+   // |+-------- Flip sprite horizontally
+   // +--------- Flip sprite vertically
+   #[derive(Serialize, Deserialize)]
+   pub struct SpriteAttributes: u8 {
+       const PALETTE1    = 0b00000001;
+       const PALETTE2    = 0b00000010;
+       const PRIORITY    = 0b00100000;
+       const FLIP_HORIZONTALLY = 0b01000000;
+       const FLIP_VERTICALLY   = 0b10000000;
+   }
+}
+
+impl SpriteAttributes {
+    pub fn palette(&self) -> u8 {
+        self.bits & 0b11
+    }
+
+    pub fn behind_background(&self) -> bool {
+        self.contains(SpriteAttributes::PRIORITY)
+    }
+
+    pub fn flip_horizontally(&self) -> bool {
+        self.contains(SpriteAttributes::FLIP_HORIZONTALLY)
+    }
+
+    pub fn flip_vertically(&self) -> bool {
+        self.contains(SpriteAttributes::FLIP_VERTICALLY)
+    }
+}
+
+// one of the eight sprites fetched for the scanline being drawn
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Sprite {
+    pub x: u8,
+    pub attr: SpriteAttributes,
+    pub pattern_lo: u8,
+    pub pattern_hi: u8,
+}
+
+impl Sprite {
+    pub fn new() -> Self {
+        Sprite {
+            x: 0xff,
+            attr: SpriteAttributes::empty(),
+            pattern_lo: 0,
+            pattern_hi: 0,
+        }
+    }
+
+    // 2-bit pattern value of the sprite at screen column `x`, 0 when transparent or not covered
+    pub fn pixel(&self, x: u16) -> u8 {
+        let col = x.wrapping_sub(self.x as u16);
+        if col >= 8 {
+            return 0;
+        }
+        let bit = 7 - col;
+        (((self.pattern_hi >> bit) & 1) << 1) | ((self.pattern_lo >> bit) & 1)
+    }
+}
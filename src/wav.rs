@@ -0,0 +1,102 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::audio::Resampler;
+
+const CHANNELS: u16 = 1;
+const BITS_PER_SAMPLE: u16 = 16;
+// resampled audio is flushed to the writer in chunks of this many samples
+const CHUNK_SAMPLES: usize = 4096;
+
+pub trait WavWriter: Write + Seek {}
+impl<T: Write + Seek> WavWriter for T {}
+
+// captures the mixed APU output into a 16-bit mono PCM RIFF file
+pub struct WavRecorder {
+    writer: Box<dyn WavWriter>,
+    resampler: Resampler,
+    chunk: Vec<i16>,
+    data_bytes: u32,
+    // first write error, reported when the recording is finished
+    error: Option<io::Error>,
+}
+
+impl WavRecorder {
+    pub fn new(
+        mut writer: Box<dyn WavWriter>,
+        clock_rate: f64,
+        sample_rate: u32,
+    ) -> io::Result<Self> {
+        // sizes are patched in by finish
+        write_header(&mut writer, sample_rate, 0)?;
+        Ok(WavRecorder {
+            writer,
+            resampler: Resampler::new(clock_rate, sample_rate),
+            chunk: vec![0; CHUNK_SAMPLES],
+            data_bytes: 0,
+            error: None,
+        })
+    }
+
+    pub fn set_clock_rate(&mut self, clock_rate: f64) {
+        self.resampler.set_clock_rate(clock_rate);
+    }
+
+    // advance one CPU cycle with the mixer output
+    pub fn tick(&mut self, amplitude: f32) {
+        self.resampler.tick(amplitude);
+        if self.resampler.samples_available() >= CHUNK_SAMPLES {
+            self.flush();
+        }
+    }
+
+    // write the remaining samples and the final chunk sizes
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush();
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_header(
+            &mut self.writer,
+            self.resampler.sample_rate(),
+            self.data_bytes,
+        )?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+
+    fn flush(&mut self) {
+        let count = self.resampler.read_samples_i16(&mut self.chunk);
+        if self.error.is_some() || count == 0 {
+            return;
+        }
+        let bytes: Vec<u8> = self.chunk[..count]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        match self.writer.write_all(&bytes) {
+            Ok(()) => self.data_bytes += bytes.len() as u32,
+            Err(error) => self.error = Some(error),
+        }
+    }
+}
+
+fn write_header(writer: &mut dyn WavWriter, sample_rate: u32, data_bytes: u32) -> io::Result<()> {
+    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_bytes).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    // PCM
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&CHANNELS.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_bytes.to_le_bytes())
+}
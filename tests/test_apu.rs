@@ -0,0 +1,99 @@
+use rust_nes::apu_impl::{apu::APU, mixer::Channel};
+
+#[test]
+fn length_counter_status() {
+    let mut apu = APU::new();
+    // writes to a disabled channel do not load its length counter
+    apu.write_register(0x4003, 0b0000_1000);
+    assert_eq!(apu.read_status() & 1, 0);
+
+    apu.write_register(0x4015, 0b0000_0001);
+    // length index 1 loads 254
+    apu.write_register(0x4003, 0b0000_1000);
+    assert_eq!(apu.read_status() & 1, 1);
+
+    // 254 half frames at two per 29830 cycles
+    for _ in 0..(127 * 29830 + 1) {
+        apu.tick();
+    }
+    assert_eq!(apu.read_status() & 1, 0);
+
+    apu.write_register(0x4003, 0b0000_1000);
+    apu.write_register(0x4015, 0);
+    assert_eq!(apu.read_status() & 1, 0);
+}
+
+#[test]
+fn pulse_output() {
+    let mut apu = APU::new();
+    // the idle triangle still drives its first step into the mixer
+    let idle = apu.output();
+
+    apu.write_register(0x4015, 0b0000_0001);
+    // 50% duty, constant volume 15
+    apu.write_register(0x4000, 0b1011_1111);
+    apu.write_register(0x4002, 0xFD);
+    apu.write_register(0x4003, 0b0000_1000);
+
+    let mut peak = idle;
+    let mut trough = idle;
+    for _ in 0..4000 {
+        apu.tick();
+        peak = peak.max(apu.output());
+        trough = trough.min(apu.output());
+    }
+    assert_eq!(trough, idle);
+    assert!(peak - idle > 0.1 && peak - idle < 0.2);
+}
+
+#[test]
+fn frame_interrupt() {
+    let mut apu = APU::new();
+    for _ in 0..29827 {
+        apu.tick();
+    }
+    assert!(!apu.irq());
+    apu.tick();
+    assert!(apu.irq());
+    // reading $4015 acknowledges the interrupt
+    assert_eq!(apu.read_status() & 0b0100_0000, 0b0100_0000);
+    assert_eq!(apu.read_status() & 0b0100_0000, 0);
+
+    // neither the inhibited 4-step nor the 5-step sequence raise it
+    for data in [0b0100_0000, 0b1000_0000] {
+        apu.write_register(0x4017, data);
+        for _ in 0..40000 {
+            apu.tick();
+            assert!(!apu.irq());
+        }
+    }
+}
+
+#[test]
+fn channel_controls() {
+    let mut apu = APU::new();
+    apu.write_register(0x4015, 0b0000_0001);
+    apu.write_register(0x4000, 0b1011_1111);
+    apu.write_register(0x4002, 0xFD);
+    apu.write_register(0x4003, 0b0000_1000);
+    // step into the high half of the duty cycle
+    while apu.output() == APU::new().output() {
+        apu.tick();
+    }
+    let full = apu.output();
+
+    apu.set_channel_muted(Channel::Pulse1, true);
+    let idle = apu.output();
+    assert!(idle < full);
+    apu.set_channel_muted(Channel::Pulse1, false);
+
+    apu.set_channel_volume(Channel::Pulse1, 0.5);
+    assert!(apu.output() > idle && apu.output() < full);
+    apu.set_channel_volume(Channel::Pulse1, 1.0);
+
+    // soloing pulse 1 drops the idle triangle level
+    apu.set_channel_solo(Channel::Pulse1, true);
+    assert!((apu.output() - (full - idle)).abs() < 1e-6);
+    apu.set_channel_solo(Channel::Triangle, true);
+    assert_eq!(apu.output(), full);
+}
@@ -0,0 +1,50 @@
+use rust_nes::{audio::Resampler, nes::Nes};
+
+const CPU_CLOCK: f64 = 1_789_773.0;
+
+fn square_wave(resampler: &mut Resampler, clocks: usize) {
+    // 1kHz between 0.0 and 0.5
+    let half_period = (CPU_CLOCK / 2000.0) as usize;
+    for clock in 0..clocks {
+        let high = (clock / half_period) % 2 == 1;
+        resampler.tick(if high { 0.5 } else { 0.0 });
+    }
+}
+
+#[test]
+fn resample_to_host_rate() {
+    let mut resampler = Resampler::new(CPU_CLOCK, 48000);
+    square_wave(&mut resampler, CPU_CLOCK as usize);
+    assert_eq!(resampler.samples_available(), 47999);
+
+    let mut samples = vec![0.0; 48000];
+    assert_eq!(resampler.read_samples(&mut samples), 47999);
+    assert_eq!(resampler.samples_available(), 0);
+    // once settled the high-pass centres the wave around zero, with some ringing at the edges
+    let settled = &samples[24000..];
+    let peak = settled.iter().cloned().fold(0.0, f32::max);
+    let trough = settled.iter().cloned().fold(0.0, f32::min);
+    assert!(peak > 0.25 && peak < 0.35);
+    assert!(trough < -0.25 && trough > -0.35);
+
+    resampler.set_rate_adjust(1.005);
+    square_wave(&mut resampler, CPU_CLOCK as usize / 2);
+    let available = resampler.samples_available();
+    assert!((24119..=24121).contains(&available));
+}
+
+#[test]
+fn console_audio_rate() {
+    let data: Vec<u8> = std::fs::read("./tests/pacman.nes").unwrap();
+    let mut nes = Nes::new(data).unwrap();
+    nes.set_sample_rate(48000);
+    for _ in 0..60 {
+        nes.run_frame();
+    }
+    // 60 NTSC frames are slightly less than a second
+    let available = nes.samples_available();
+    assert!((47800..48000).contains(&available));
+
+    let mut samples = vec![0; available];
+    assert_eq!(nes.read_samples_i16(&mut samples), available);
+}
@@ -0,0 +1,18 @@
+use rust_nes::clock::{Clock, Region};
+
+fn count_ticks(region: Region, master_cycles: u64) -> (u64, u64) {
+    let mut clock = Clock::new(region);
+    let (mut cpu, mut ppu) = (0, 0);
+    while let Some(tick) = clock.advance(master_cycles) {
+        cpu += tick.cpu as u64;
+        ppu += tick.ppu as u64;
+    }
+    (cpu, ppu)
+}
+
+#[test]
+fn ppu_cpu_ratio() {
+    assert_eq!(count_ticks(Region::NTSC, 12 * 1000), (1000, 3000));
+    assert_eq!(count_ticks(Region::PAL, 16 * 5 * 1000), (5000, 16000));
+    assert_eq!(count_ticks(Region::DENDY, 15 * 1000), (1000, 3000));
+}
@@ -0,0 +1,56 @@
+use rust_nes::nes::Nes;
+
+// NROM image running `code` from $8000 with page $02 partly filled
+fn program(code: &[u8]) -> Vec<u8> {
+    let mut prg = vec![
+        0xA9, 0x11, // LDA #$11
+        0x8D, 0x00, 0x02, // STA $0200
+        0xA9, 0x22, // LDA #$22
+        0x8D, 0xFF, 0x02, // STA $02FF
+    ];
+    prg.extend(code);
+    prg.extend([
+        0xA9, 0x02, // LDA #$02
+        0x8D, 0x14, 0x40, // STA $4014
+        0x4C, 0x00, 0x90, // JMP $9000
+    ]);
+    prg.resize(0x4000, 0xEA);
+    // reset vector
+    prg[0x3FFC] = 0x00;
+    prg[0x3FFD] = 0x80;
+
+    let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01];
+    data.resize(16, 0);
+    data.extend(prg);
+    data.extend(vec![0; 0x2000]);
+    data
+}
+
+// CPU cycles from the start of STA $4014 to the next instruction, `code` holds
+// `instructions` instructions run before the DMA
+fn dma_cycles(code: &[u8], instructions: usize) -> (u64, Nes) {
+    let mut nes = Nes::new(program(code)).unwrap();
+    // step_instruction returns as an instruction begins, the first call at the
+    // first instruction after reset
+    for _ in 0..4 + instructions + 2 {
+        nes.step_instruction();
+    }
+    let cycles = nes.step_instruction() / 12;
+    (cycles, nes)
+}
+
+#[test]
+fn oam_dma() {
+    // reset starts on cycle 1, so STA $4014 ends on an odd cycle
+    let (cycles, nes) = dma_cycles(&[], 0);
+    assert_eq!(cycles, 4 + 514);
+    assert_eq!(nes.oam()[0], 0x11);
+    assert_eq!(nes.oam()[0xFF], 0x22);
+    assert!(nes.oam()[1..0xFF].iter().all(|&b| b == 0));
+
+    // a 3-cycle LDA $00 in front moves the DMA to an even cycle
+    let (cycles, nes) = dma_cycles(&[0xA5, 0x00], 1);
+    assert_eq!(cycles, 4 + 513);
+    assert_eq!(nes.oam()[0], 0x11);
+    assert_eq!(nes.oam()[0xFF], 0x22);
+}
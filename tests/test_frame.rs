@@ -1,3 +1,5 @@
+#![allow(clippy::ptr_arg, clippy::unnecessary_cast, clippy::assign_op_pattern)]
+
 use rust_nes::consts::{HEIGHT, SYSTEM_PALLETE, WIDTH};
 use rust_nes::ppu_impl::ppu::{Frame, PPU};
 
@@ -5,8 +7,13 @@ use rust_nes::ppu_impl::ppu::{Frame, PPU};
 fn main() {
     let data: Vec<u8> = std::fs::read("./tests/pacman.nes").unwrap();
     let mut ppu = PPU::new();
-    ppu.load_rom(data.clone());
-    let tile_frame = show_tile(&ppu.mem.rom.unwrap().chr.unwrap(), 0);
+    ppu.load_rom(data.clone()).unwrap();
+    let rom = ppu.mem.rom.expect("not load rom!");
+    let chr: Vec<u8> = (0..0x2000)
+        .map(|addr| rom.borrow_mut().ppu_read(addr))
+        .collect();
+    let tile_frame = show_tile(&chr, 0);
+    assert!(tile_frame.data.iter().any(|&b| b != 0));
 }
 
 pub fn show_tile(chr_rom: &Vec<u8>, bank: usize) -> Frame {
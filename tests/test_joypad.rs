@@ -0,0 +1,38 @@
+use rust_nes::joypad::{Joypad, JoypadButton};
+
+#[test]
+fn shift_out_buttons() {
+    let mut joypad = Joypad::new();
+    joypad.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+    joypad.set_button_pressed_status(JoypadButton::START, true);
+    joypad.set_button_pressed_status(JoypadButton::RIGHT, true);
+
+    joypad.write(1);
+    // while strobe is high every read reports button A
+    assert_eq!(joypad.read(), 1);
+    assert_eq!(joypad.read(), 1);
+    joypad.write(0);
+
+    let bits: Vec<u8> = (0..8).map(|_| joypad.read()).collect();
+    assert_eq!(bits, vec![1, 0, 0, 1, 0, 0, 0, 1]);
+    assert_eq!(joypad.read(), 1);
+}
+
+#[test]
+fn latch_on_strobe_fall() {
+    let mut joypad = Joypad::new();
+    joypad.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+    joypad.write(1);
+    joypad.write(0);
+
+    // changes after the latch only show up in the next report
+    assert_eq!(joypad.read(), 1);
+    joypad.set_button_pressed_status(JoypadButton::BUTTON_A, false);
+    joypad.set_button_pressed_status(JoypadButton::BUTTON_B, true);
+    assert_eq!(joypad.read(), 0);
+
+    joypad.write(1);
+    joypad.write(0);
+    assert_eq!(joypad.read(), 0);
+    assert_eq!(joypad.read(), 1);
+}
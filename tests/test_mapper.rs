@@ -0,0 +1,416 @@
+use rust_nes::clock::Region;
+use rust_nes::nes::Nes;
+use rust_nes::ppu_impl::ppu::PPU;
+use rust_nes::ROM::{ConsoleType, Mirroring, RomError, ROM};
+
+// iNES image whose 16KB PRG banks and 4KB CHR banks start with their bank number,
+// the rest of PRG is $FF
+fn ines(mapper: u8, prg_banks: u8, chr_banks: u8) -> Vec<u8> {
+    let mut data = vec![
+        0x4E,
+        0x45,
+        0x53,
+        0x1A,
+        prg_banks,
+        chr_banks,
+        mapper << 4,
+        mapper & 0xF0,
+    ];
+    data.resize(16, 0);
+    for bank in 0..prg_banks as usize {
+        let mut prg = vec![0xFF; 0x4000];
+        prg[0] = bank as u8;
+        data.extend(prg);
+    }
+    for bank in 0..chr_banks as usize * 2 {
+        let mut chr = vec![0; 0x1000];
+        chr[0] = bank as u8;
+        data.extend(chr);
+    }
+    data
+}
+
+fn read(rom: &mut ROM, address: u16) -> u8 {
+    rom.cpu_read(address).expect("open bus")
+}
+
+fn mmc1_write(rom: &mut ROM, address: u16, value: u8) {
+    for bit in 0..5 {
+        rom.cpu_write(address, (value >> bit) & 1);
+    }
+}
+
+#[test]
+fn mmc1_banking() {
+    let mut rom = ROM::new(ines(1, 8, 4)).unwrap();
+
+    // power on: last bank fixed at $C000
+    assert_eq!(read(&mut rom, 0xC000), 7);
+    mmc1_write(&mut rom, 0xE000, 3);
+    assert_eq!(read(&mut rom, 0x8000), 3);
+    assert_eq!(read(&mut rom, 0xC000), 7);
+
+    // 32KB mode ignores the low bit of the bank number
+    mmc1_write(&mut rom, 0x8000, 0b0_0010);
+    assert_eq!(read(&mut rom, 0x8000), 2);
+    assert_eq!(read(&mut rom, 0xC000), 3);
+    assert_eq!(rom.mirroring(), Mirroring::VERTICAL);
+
+    // a write with bit 7 set resets the shift register
+    rom.cpu_write(0x8000, 1);
+    rom.cpu_write(0x8000, 0x80);
+    mmc1_write(&mut rom, 0xE000, 5);
+    assert_eq!(read(&mut rom, 0x8000), 5);
+
+    // two 4KB CHR banks
+    mmc1_write(&mut rom, 0x8000, 0b1_0011);
+    mmc1_write(&mut rom, 0xA000, 5);
+    mmc1_write(&mut rom, 0xC000, 2);
+    assert_eq!(rom.ppu_read(0x0000), 5);
+    assert_eq!(rom.ppu_read(0x1000), 2);
+    assert_eq!(rom.mirroring(), Mirroring::HORIZONTAL);
+
+    // PRG RAM is disabled by bit 4 of the PRG bank register, reads are open bus
+    rom.cpu_write(0x6000, 0x42);
+    assert_eq!(read(&mut rom, 0x6000), 0x42);
+    mmc1_write(&mut rom, 0xE000, 0b1_0000);
+    assert_eq!(rom.cpu_read(0x6000), None);
+}
+
+#[test]
+fn uxrom_banking() {
+    let mut rom = ROM::new(ines(2, 8, 1)).unwrap();
+    assert_eq!(read(&mut rom, 0x8000), 0);
+    assert_eq!(read(&mut rom, 0xC000), 7);
+    rom.cpu_write(0x8001, 5);
+    assert_eq!(read(&mut rom, 0x8000), 5);
+    assert_eq!(read(&mut rom, 0xC000), 7);
+}
+
+#[test]
+fn nes2_header() {
+    // UxROM submapper 1, 32KB PRG in exponent-multiplier form, 8KB battery-backed PRG-RAM
+    let mut data = vec![
+        0x4E, 0x45, 0x53, 0x1A, 0x3C, 0x01, 0x23, 0x08, 0x10, 0x0F, 0x70, 0x00, 0x03, 0x00, 0x01,
+        0x01,
+    ];
+    data.extend(&ines(2, 2, 1)[16..]);
+    let mut rom = ROM::new(data).unwrap();
+
+    let header = rom.header();
+    assert!(header.nes2);
+    assert_eq!((header.mapper, header.submapper), (2, 1));
+    assert_eq!(header.prg_rom_size, 0x8000);
+    assert_eq!(header.chr_rom_size, 0x2000);
+    assert_eq!((header.prg_ram_size, header.prg_nvram_size), (0, 0x2000));
+    assert_eq!((header.chr_ram_size, header.chr_nvram_size), (0, 0));
+    assert!(header.battery);
+    assert_eq!(header.region, Region::DENDY);
+    assert_eq!(header.console_type, ConsoleType::NES);
+    assert_eq!((header.misc_roms, header.expansion_device), (1, 1));
+    assert_eq!(rom.mirroring(), Mirroring::VERTICAL);
+
+    // submapper 1 boards have no bus conflicts
+    rom.cpu_write(0x8000, 1);
+    assert_eq!(read(&mut rom, 0x8000), 1);
+}
+
+#[test]
+fn rom_errors() {
+    let error = |data: Vec<u8>| ROM::new(data).err();
+    assert_eq!(error(b"PK\x03\x04".to_vec()), Some(RomError::BadMagic));
+    assert_eq!(
+        error(ines(0, 2, 1)[..10].to_vec()),
+        Some(RomError::BadMagic)
+    );
+
+    let data = ines(0, 2, 1);
+    assert_eq!(
+        error(data[..0x6000].to_vec()),
+        Some(RomError::TruncatedPrg {
+            expected: 0x8000,
+            actual: 0x5FF0
+        })
+    );
+    assert_eq!(
+        error(data[..0x9000].to_vec()),
+        Some(RomError::TruncatedChr {
+            expected: 0x2000,
+            actual: 0x0FF0
+        })
+    );
+    assert_eq!(error(ines(5, 2, 1)), Some(RomError::UnsupportedMapper(5)));
+
+    // NES 2.0 header with 4KB of PRG in exponent-multiplier form
+    for mapper in [1, 2, 4] {
+        let mut data = vec![
+            0x4E,
+            0x45,
+            0x53,
+            0x1A,
+            0x30,
+            0x01,
+            mapper << 4,
+            0x08,
+            0x00,
+            0x0F,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+        data.resize(16 + 0x1000 + 0x2000, 0);
+        assert!(matches!(error(data), Some(RomError::UnsupportedFormat(_))));
+    }
+}
+
+#[test]
+fn archaic_ines_header() {
+    // bytes 7-15 are ignored, the mapper comes from the high nibble of byte 6 only
+    let mut data = ines(0, 2, 1);
+    data[6] |= 0b1;
+    data[7..16].copy_from_slice(b"DiskDude!");
+    let mut rom = ROM::new(data).unwrap();
+    assert_eq!(rom.header().mapper, 0);
+    assert!(!rom.header().nes2);
+    assert_eq!(rom.region(), Region::NTSC);
+    assert_eq!(rom.mirroring(), Mirroring::VERTICAL);
+    assert_eq!(read(&mut rom, 0xC000), 1);
+}
+
+#[test]
+fn battery_ram() {
+    // NROM boards carry no PRG-RAM, $6000 is open bus
+    let mut rom = ROM::new(ines(0, 2, 1)).unwrap();
+    assert_eq!(rom.cpu_read(0x6000), None);
+    assert!(rom.battery_ram().is_none());
+
+    let mut data = ines(1, 8, 4);
+    data[6] |= 0b10;
+    let mut rom = ROM::new(data.clone()).unwrap();
+    rom.cpu_write(0x6000, 0x12);
+    rom.cpu_write(0x7FFF, 0x34);
+    let save = rom.battery_ram().unwrap().to_vec();
+    assert_eq!(save.len(), 0x2000);
+
+    let mut rom = ROM::new(data).unwrap();
+    rom.load_battery_ram(&save);
+    assert_eq!(read(&mut rom, 0x6000), 0x12);
+    assert_eq!(read(&mut rom, 0x7FFF), 0x34);
+
+    // NES 2.0 MMC1 with 8KB of volatile PRG-RAM in front of 8KB battery-backed
+    let mut data = ines(1, 8, 4);
+    data[6] |= 0b10;
+    data[7] |= 0x08;
+    data[10] = 0x77;
+    let mut rom = ROM::new(data).unwrap();
+    rom.cpu_write(0x6000, 0x12);
+    assert_eq!(rom.battery_ram(), Some(&[0; 0x2000][..]));
+    rom.load_battery_ram(&[0x56; 0x4000]);
+    assert_eq!(read(&mut rom, 0x6000), 0x12);
+    assert_eq!(rom.battery_ram(), Some(&[0x56; 0x2000][..]));
+
+    // a state with less PRG-RAM fills the front and keeps the header's size
+    let mut small = ROM::new(ines(1, 8, 4)).unwrap();
+    small.cpu_write(0x6000, 0x34);
+    rom.load_state(small.save_state()).unwrap();
+    assert_eq!(read(&mut rom, 0x6000), 0x34);
+    assert_eq!(rom.battery_ram(), Some(&[0x56; 0x2000][..]));
+}
+
+#[test]
+fn cnrom_bus_conflict() {
+    let mut rom = ROM::new(ines(3, 2, 4)).unwrap();
+    rom.cpu_write(0x8001, 2);
+    assert_eq!(rom.ppu_read(0x0000), 4);
+    // $8000 holds 0, so the ROM pulls every bit of the written value low
+    rom.cpu_write(0x8000, 3);
+    assert_eq!(rom.ppu_read(0x0000), 0);
+    rom.cpu_write(0x8001, 3);
+    // 8KB bank 3 starts with 4KB bank 6
+    assert_eq!(rom.ppu_read(0x0000), 6);
+    assert_eq!(rom.ppu_read(0x1000), 7);
+}
+
+#[test]
+fn axrom_banking() {
+    let mut rom = ROM::new(ines(7, 8, 1)).unwrap();
+    assert_eq!(rom.mirroring(), Mirroring::SINGLE_SCREEN_LOWER_BANK);
+    // no bus conflicts by default
+    rom.cpu_write(0x8000, 0b1_0010);
+    assert_eq!(read(&mut rom, 0x8000), 4);
+    assert_eq!(read(&mut rom, 0xC000), 5);
+    assert_eq!(rom.mirroring(), Mirroring::SINGLE_SCREEN_UPPER_BANK);
+}
+
+// 8KB PRG banks and 1KB CHR banks start with their bank number
+fn mmc3(prg_8k_banks: usize, chr_1k_banks: usize) -> Vec<u8> {
+    let mut data = vec![0x4E, 0x45, 0x53, 0x1A];
+    data.push((prg_8k_banks / 2) as u8);
+    data.push((chr_1k_banks / 8) as u8);
+    data.push(0x40);
+    data.resize(16, 0);
+    for bank in 0..prg_8k_banks {
+        let mut prg = vec![0xFF; 0x2000];
+        prg[0] = bank as u8;
+        data.extend(prg);
+    }
+    for bank in 0..chr_1k_banks {
+        let mut chr = vec![0; 0x0400];
+        chr[0] = bank as u8;
+        data.extend(chr);
+    }
+    data
+}
+
+#[test]
+fn mmc3_banking() {
+    let mut rom = ROM::new(mmc3(16, 64)).unwrap();
+    for (register, bank) in [(0, 8), (1, 12), (2, 1), (5, 7), (6, 3), (7, 4)] {
+        rom.cpu_write(0x8000, register);
+        rom.cpu_write(0x8001, bank);
+    }
+    assert_eq!(read(&mut rom, 0x8000), 3);
+    assert_eq!(read(&mut rom, 0xA000), 4);
+    assert_eq!(read(&mut rom, 0xC000), 14);
+    assert_eq!(read(&mut rom, 0xE000), 15);
+    assert_eq!(rom.ppu_read(0x0000), 8);
+    assert_eq!(rom.ppu_read(0x0400), 9);
+    assert_eq!(rom.ppu_read(0x0800), 12);
+    assert_eq!(rom.ppu_read(0x1000), 1);
+    assert_eq!(rom.ppu_read(0x1C00), 7);
+
+    // PRG mode 1 swaps $8000 and $C000, CHR inversion swaps the pattern tables
+    rom.cpu_write(0x8000, 0b1100_0000);
+    assert_eq!(read(&mut rom, 0x8000), 14);
+    assert_eq!(read(&mut rom, 0xC000), 3);
+    assert_eq!(rom.ppu_read(0x0000), 1);
+    assert_eq!(rom.ppu_read(0x1000), 8);
+
+    rom.cpu_write(0xA000, 1);
+    assert_eq!(rom.mirroring(), Mirroring::HORIZONTAL);
+}
+
+#[test]
+fn mmc3_scanline_irq() {
+    let mut rom = ROM::new(mmc3(16, 64)).unwrap();
+    // IRQ after every third scanline
+    rom.cpu_write(0xC000, 2);
+    rom.cpu_write(0xC001, 0);
+    rom.cpu_write(0xE001, 0);
+
+    // background at $0000, sprites at $1000: one A12 rise per scanline
+    let mut cycle = 0;
+    let mut scanline = |rom: &mut ROM| {
+        rom.ppu_address(0x0000, cycle + 5);
+        rom.ppu_address(0x1000, cycle + 257);
+        // the drop between two sprite fetches is filtered out
+        rom.ppu_address(0x2000, cycle + 259);
+        rom.ppu_address(0x1010, cycle + 261);
+        cycle += 341;
+    };
+
+    // the first rise reloads the counter, the IRQ fires when it reaches zero
+    for _ in 0..2 {
+        scanline(&mut rom);
+        assert!(!rom.irq());
+    }
+    scanline(&mut rom);
+    assert!(rom.irq());
+
+    // disabling acknowledges
+    rom.cpu_write(0xE000, 0);
+    assert!(!rom.irq());
+    rom.cpu_write(0xE001, 0);
+    for _ in 0..2 {
+        scanline(&mut rom);
+        assert!(!rom.irq());
+    }
+    scanline(&mut rom);
+    assert!(rom.irq());
+}
+
+#[test]
+fn mmc3_irq_from_ppudata() {
+    let mut ppu = PPU::new();
+    ppu.load_rom(mmc3(16, 64)).unwrap();
+    let rom = ppu.mem.rom.clone().unwrap();
+    rom.borrow_mut().cpu_write(0xC000, 0);
+    rom.borrow_mut().cpu_write(0xE001, 0);
+
+    ppu.write_register(0x2006, 0x0F);
+    ppu.write_register(0x2006, 0xFF);
+    for _ in 0..12 {
+        ppu.tick();
+    }
+    // the second write puts $1000 on the bus, a rising A12 edge
+    ppu.write_register(0x2007, 0);
+    assert!(!rom.borrow().irq());
+    ppu.write_register(0x2007, 0);
+    assert!(rom.borrow().irq());
+}
+
+#[test]
+fn mapper_state() {
+    let mut rom = ROM::new(ines(1, 8, 4)).unwrap();
+    mmc1_write(&mut rom, 0xE000, 3);
+    mmc1_write(&mut rom, 0xA000, 4);
+    let state = rom.save_state();
+
+    mmc1_write(&mut rom, 0xE000, 6);
+    mmc1_write(&mut rom, 0xA000, 2);
+    rom.load_state(state).unwrap();
+    assert_eq!(read(&mut rom, 0x8000), 3);
+    assert_eq!(rom.ppu_read(0x0000), 4);
+
+    // MMC1 registers do not decode as MMC3 ones
+    let mut mmc3_rom = ROM::new(mmc3(16, 64)).unwrap();
+    assert!(mmc3_rom.load_state(rom.save_state()).is_err());
+    assert_eq!(read(&mut mmc3_rom, 0xE000), 15);
+}
+
+#[test]
+fn nes_save_state() {
+    // MMC3 image running from the fixed bank at $E000
+    let mut data = mmc3(16, 64);
+    let code = [
+        0xA9, 0x06, 0x8D, 0x00, 0x80, 0xA9, 0x03, 0x8D, 0x01, 0x80, // R6 = 3
+        0xA9, 0x07, 0x8D, 0x00, 0x80, 0xA9, 0x04, 0x8D, 0x01, 0x80, // R7 = 4
+        0xA9, 0x05, 0x8D, 0x00, 0xC0, 0x8D, 0x01, 0xC0, 0x8D, 0x01, 0xE0, // IRQ on
+        // the state is saved here: copy $8000 and $A000 to OAM
+        0xAD, 0x00, 0x80, 0x8D, 0x00, 0x02, 0xAD, 0x00, 0xA0, 0x8D, 0x01, 0x02, //
+        0xA9, 0x02, 0x8D, 0x14, 0x40, //
+        // then switch to banks 9 and 10 and disable the IRQ
+        0xA9, 0x06, 0x8D, 0x00, 0x80, 0xA9, 0x09, 0x8D, 0x01, 0x80, //
+        0xA9, 0x07, 0x8D, 0x00, 0x80, 0xA9, 0x0A, 0x8D, 0x01, 0x80, //
+        0x8D, 0x00, 0xE0, //
+        0x4C, 0x47, 0xE1, // JMP $E147
+    ];
+    let last_bank = 16 + 15 * 0x2000;
+    data[last_bank + 0x100..last_bank + 0x100 + code.len()].copy_from_slice(&code);
+    // reset vector
+    data[last_bank + 0x1FFC] = 0x00;
+    data[last_bank + 0x1FFD] = 0xE1;
+
+    let mut nes = Nes::new(data).unwrap();
+    for _ in 0..1 + 12 {
+        nes.step_instruction();
+    }
+    let state = nes.save_state();
+
+    for _ in 0..6 + 9 + 2 {
+        nes.step_instruction();
+    }
+    assert_eq!(nes.oam()[0..2], [3, 4]);
+
+    assert!(nes.load_state(b"not a save state").is_err());
+    nes.load_state(&state).unwrap();
+    assert!(nes.save_state() == state);
+    assert_eq!(nes.oam()[0..2], [0, 0]);
+    for _ in 0..6 + 1 {
+        nes.step_instruction();
+    }
+    assert_eq!(nes.oam()[0..2], [3, 4]);
+}
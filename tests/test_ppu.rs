@@ -0,0 +1,509 @@
+use rust_nes::consts::{SYSTEM_PALLETE, WIDTH};
+use rust_nes::ppu_impl::ppu::PPU;
+
+// NROM image with vertical mirroring around the given 8KB of pattern tables
+fn nrom(chr: Vec<u8>) -> Vec<u8> {
+    let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x01];
+    data.resize(16, 0);
+    data.extend(vec![0; 0x4000]);
+    data.extend(chr);
+    data
+}
+
+fn write_vram(ppu: &mut PPU, addr: u16, data: u8) {
+    ppu.write_register(0x2006, (addr >> 8) as u8);
+    ppu.write_register(0x2006, (addr & 0xff) as u8);
+    ppu.write_register(0x2007, data);
+}
+
+fn read_vram(ppu: &mut PPU, addr: u16) -> u8 {
+    ppu.write_register(0x2006, (addr >> 8) as u8);
+    ppu.write_register(0x2006, (addr & 0xff) as u8);
+    // the first read returns the stale PPUDATA buffer
+    ppu.read_register(0x2007);
+    ppu.read_register(0x2007)
+}
+
+// tick until the first `lines` scanlines are drawn
+fn render_lines(ppu: &mut PPU, lines: u16) {
+    while ppu.scanline() < lines {
+        ppu.tick();
+    }
+}
+
+fn pixel(ppu: &PPU, x: usize, y: usize) -> (u8, u8, u8) {
+    let base = (y * WIDTH + x) * 3;
+    let data = &ppu.frame.data;
+    (data[base], data[base + 1], data[base + 2])
+}
+
+fn color(index: u8) -> (u8, u8, u8) {
+    SYSTEM_PALLETE[index as usize]
+}
+
+const BACKDROP: u8 = 0x0F;
+const BG_COLOR: u8 = 0x21;
+
+// one frame of a diagonal tile 1 at column 3, row 2 of the first nametable
+fn background_frame(fine_x: u8) -> PPU {
+    let mut chr = vec![0; 0x2000];
+    for row in 0..8 {
+        chr[0x10 + row] = 0x80 >> row;
+    }
+    let mut ppu = PPU::new();
+    ppu.load_rom(nrom(chr)).unwrap();
+    write_vram(&mut ppu, 0x2043, 1);
+    write_vram(&mut ppu, 0x3F00, BACKDROP);
+    write_vram(&mut ppu, 0x3F01, BG_COLOR);
+    // point v back at the first nametable
+    ppu.write_register(0x2006, 0);
+    ppu.write_register(0x2006, 0);
+    ppu.write_register(0x2005, fine_x);
+    ppu.write_register(0x2005, 0);
+    ppu.write_register(0x2001, 0b0000_1010);
+    render_lines(&mut ppu, 32);
+    ppu
+}
+
+#[test]
+fn background() {
+    let ppu = background_frame(0);
+    for i in 0..8 {
+        assert_eq!(pixel(&ppu, 24 + i, 16 + i), color(BG_COLOR));
+        assert_eq!(pixel(&ppu, 23 + i, 16 + i), color(BACKDROP));
+        assert_eq!(pixel(&ppu, 25 + i, 16 + i), color(BACKDROP));
+    }
+
+    // a fine X scroll of 3 moves the tile 3 pixels left
+    let ppu = background_frame(3);
+    for i in 0..8 {
+        assert_eq!(pixel(&ppu, 21 + i, 16 + i), color(BG_COLOR));
+        assert_eq!(pixel(&ppu, 20 + i, 16 + i), color(BACKDROP));
+        assert_eq!(pixel(&ppu, 22 + i, 16 + i), color(BACKDROP));
+    }
+}
+
+#[test]
+fn register_reads() {
+    let mut ppu = PPU::new();
+    ppu.load_rom(nrom(vec![0; 0x2000])).unwrap();
+
+    // reading $2002 resets the $2005/$2006 write latch
+    ppu.write_register(0x2006, 0x21);
+    ppu.read_register(0x2002);
+    write_vram(&mut ppu, 0x2305, 0x77);
+    assert_eq!(read_vram(&mut ppu, 0x2305), 0x77);
+
+    // bits 2-4 of the sprite attribute byte read back as 0
+    ppu.write_register(0x2003, 1);
+    ppu.write_register(0x2004, 0xFF);
+    ppu.write_register(0x2004, 0xFF);
+    ppu.write_register(0x2003, 1);
+    assert_eq!(ppu.read_register(0x2004), 0xFF);
+    ppu.write_register(0x2003, 2);
+    assert_eq!(ppu.read_register(0x2004), 0xE3);
+
+    // write-only registers and the low bits of $2002 return the last bus value
+    ppu.write_register(0x2003, 0x5A);
+    assert_eq!(ppu.read_register(0x2000), 0x5A);
+    assert_eq!(ppu.read_register(0x2005), 0x5A);
+    assert_eq!(ppu.read_register(0x2002) & 0b0001_1111, 0x1A);
+}
+
+// the $2006/$2005/$2005/$2006 sequence games use for mid-frame scroll splits
+fn scroll_split(ppu: &mut PPU, nametable: u8, x: u8, y: u8) {
+    ppu.write_register(0x2006, nametable << 2);
+    ppu.write_register(0x2005, y);
+    ppu.write_register(0x2005, x);
+    ppu.write_register(0x2006, ((y & 0xF8) << 2) | (x >> 3));
+}
+
+#[test]
+fn scroll_registers() {
+    let mut ppu = PPU::new();
+    ppu.load_rom(nrom(vec![0; 0x2000])).unwrap();
+
+    // fine Y 2, coarse Y 11, nametable 1, coarse X 16: v = $2570
+    scroll_split(&mut ppu, 1, 0x83, 0x5A);
+    ppu.write_register(0x2007, 0x99);
+    assert_eq!(read_vram(&mut ppu, 0x2570), 0x99);
+
+    // the nametable bits of $2000 land in t between the two $2006 writes
+    ppu.write_register(0x2006, 0x20);
+    ppu.write_register(0x2000, 0b01);
+    ppu.write_register(0x2006, 0x00);
+    ppu.write_register(0x2007, 0x42);
+    ppu.write_register(0x2000, 0);
+    assert_eq!(read_vram(&mut ppu, 0x2400), 0x42);
+    assert_eq!(read_vram(&mut ppu, 0x2000), 0);
+
+    // bit 2 of $2000 steps v by 32
+    ppu.write_register(0x2000, 0b100);
+    write_vram(&mut ppu, 0x2100, 1);
+    ppu.write_register(0x2007, 2);
+    ppu.write_register(0x2000, 0);
+    assert_eq!(read_vram(&mut ppu, 0x2120), 2);
+    assert_eq!(read_vram(&mut ppu, 0x2101), 0);
+}
+
+#[test]
+fn rendering_increments() {
+    let mut chr = vec![0; 0x2000];
+    chr[0x1460] = 0x11;
+    let mut ppu = PPU::new();
+    ppu.load_rom(nrom(chr)).unwrap();
+
+    // while rendering a PPUDATA access bumps coarse X and fine Y instead: coarse X
+    // 31 -> 0 switches the horizontal nametable, $007F becomes $1460
+    scroll_split(&mut ppu, 0, 0xF8, 0x18);
+    ppu.write_register(0x2001, 0b0000_1000);
+    ppu.read_register(0x2007);
+    ppu.write_register(0x2001, 0);
+    ppu.read_register(0x2007);
+    assert_eq!(ppu.read_register(0x2007), 0x11);
+}
+
+const SPRITE_COLOR1: u8 = 0x16;
+const SPRITE_COLOR2: u8 = 0x2A;
+const SPRITE_PALETTE1: u8 = 0x12;
+
+fn write_oam(ppu: &mut PPU, sprites: &[[u8; 4]]) {
+    ppu.write_register(0x2003, 0);
+    for n in 0..64 {
+        for &byte in sprites.get(n).unwrap_or(&[0xFF; 4]) {
+            ppu.write_register(0x2004, byte);
+        }
+    }
+}
+
+// Solid tiles 1 and 4, tiles 2 and 3 of the $1000 table filled on the left half with
+// color 1 and 2. OAM is filled through $2003/$2004 with the remaining sprites parked
+// off screen, `background` lists the nametable entries set to tile 4.
+fn sprite_ppu(sprites: &[[u8; 4]], background: &[u16]) -> PPU {
+    let mut chr = vec![0; 0x2000];
+    chr[0x10..0x18].fill(0xFF);
+    chr[0x40..0x48].fill(0xFF);
+    chr[0x1020..0x1028].fill(0xF0);
+    chr[0x1038..0x1040].fill(0xF0);
+    let mut ppu = PPU::new();
+    ppu.load_rom(nrom(chr)).unwrap();
+
+    for &addr in background {
+        write_vram(&mut ppu, addr, 4);
+    }
+    write_vram(&mut ppu, 0x3F00, BACKDROP);
+    write_vram(&mut ppu, 0x3F01, BG_COLOR);
+    write_vram(&mut ppu, 0x3F11, SPRITE_COLOR1);
+    write_vram(&mut ppu, 0x3F12, SPRITE_COLOR2);
+    write_vram(&mut ppu, 0x3F15, SPRITE_PALETTE1);
+    write_oam(&mut ppu, sprites);
+    ppu.write_register(0x2006, 0);
+    ppu.write_register(0x2006, 0);
+    ppu
+}
+
+#[test]
+fn sprite_evaluation() {
+    // nine sprites on scanlines 21 - 28, only the first eight are drawn
+    let sprites: Vec<[u8; 4]> = (0..9).map(|i| [20, 1, 0, 8 + i * 16]).collect();
+    let mut ppu = sprite_ppu(&sprites, &[]);
+    ppu.write_register(0x2001, 0b0001_1110);
+    render_lines(&mut ppu, 32);
+
+    assert_eq!(ppu.read_register(0x2002) & 0b0110_0000, 0b0010_0000);
+    for i in 0..8 {
+        let x = 8 + i * 16;
+        assert_eq!(pixel(&ppu, x, 21), color(SPRITE_COLOR1));
+        assert_eq!(pixel(&ppu, x + 7, 28), color(SPRITE_COLOR1));
+        assert_eq!(pixel(&ppu, x + 8, 21), color(BACKDROP));
+    }
+    assert_eq!(pixel(&ppu, 136, 21), color(BACKDROP));
+    // sprites are drawn one line below their Y coordinate
+    assert_eq!(pixel(&ppu, 8, 20), color(BACKDROP));
+    assert_eq!(pixel(&ppu, 8, 29), color(BACKDROP));
+}
+
+#[test]
+fn sprite_overflow_bug() {
+    // after eight sprites the scan reads the tile byte of sprite 9 as its Y, so the
+    // ninth sprite on the line is missed...
+    let mut sprites: Vec<[u8; 4]> = (0..8).map(|i| [40, 1, 0, i * 8]).collect();
+    sprites.push([0xFF; 4]);
+    sprites.push([40, 1, 0, 200]);
+    let mut ppu = sprite_ppu(&sprites, &[]);
+    ppu.write_register(0x2001, 0b0001_1110);
+    render_lines(&mut ppu, 50);
+    assert_eq!(ppu.read_register(0x2002) & 0b0010_0000, 0);
+    assert_eq!(pixel(&ppu, 200, 41), color(BACKDROP));
+
+    // ...and a tile byte that matches the line sets the flag for a sprite that is not there
+    sprites[9] = [0xFF, 40, 0xFF, 0xFF];
+    let mut ppu = sprite_ppu(&sprites, &[]);
+    ppu.write_register(0x2001, 0b0001_1110);
+    render_lines(&mut ppu, 50);
+    assert_eq!(ppu.read_register(0x2002) & 0b0010_0000, 0b0010_0000);
+}
+
+#[test]
+fn sprite_8x16() {
+    // tile $03 selects the $1000 table, tile 2 on top and tile 3 below
+    let mut ppu = sprite_ppu(&[[50, 0x03, 0x00, 16], [50, 0x03, 0xC0, 48]], &[]);
+    ppu.write_register(0x2000, 0b0010_0000);
+    ppu.write_register(0x2001, 0b0001_1110);
+    render_lines(&mut ppu, 70);
+
+    for y in 51..59 {
+        assert_eq!(pixel(&ppu, 16, y), color(SPRITE_COLOR1));
+        assert_eq!(pixel(&ppu, 20, y), color(BACKDROP));
+        // flipped both ways: the bottom tile shows on top, its right half filled
+        assert_eq!(pixel(&ppu, 48, y), color(BACKDROP));
+        assert_eq!(pixel(&ppu, 55, y), color(SPRITE_COLOR2));
+    }
+    for y in 59..67 {
+        assert_eq!(pixel(&ppu, 19, y), color(SPRITE_COLOR2));
+        assert_eq!(pixel(&ppu, 23, y), color(BACKDROP));
+        assert_eq!(pixel(&ppu, 52, y), color(SPRITE_COLOR1));
+    }
+    assert_eq!(pixel(&ppu, 16, 67), color(BACKDROP));
+}
+
+#[test]
+fn sprite_priority() {
+    let sprites = [
+        [20, 1, 0x00, 100],
+        [20, 1, 0x01, 96],
+        // behind the background tile at column 12, row 10
+        [79, 1, 0x20, 92],
+        [79, 1, 0x01, 92],
+    ];
+    let mut ppu = sprite_ppu(&sprites, &[0x214C]);
+    ppu.write_register(0x2001, 0b0001_1110);
+    render_lines(&mut ppu, 90);
+
+    // where sprites overlap the lower OAM index wins
+    assert_eq!(pixel(&ppu, 96, 21), color(SPRITE_PALETTE1));
+    assert_eq!(pixel(&ppu, 100, 21), color(SPRITE_COLOR1));
+    assert_eq!(pixel(&ppu, 104, 21), color(SPRITE_COLOR1));
+
+    // a back-priority sprite still hides the front-priority sprites after it
+    assert_eq!(pixel(&ppu, 92, 80), color(SPRITE_COLOR1));
+    assert_eq!(pixel(&ppu, 96, 80), color(BG_COLOR));
+    assert_eq!(pixel(&ppu, 99, 87), color(BG_COLOR));
+}
+
+#[test]
+fn sprite_zero_hit() {
+    // sprite 0 over the background tile at column 0, row 10, hidden by the left
+    // column mask
+    let mut ppu = sprite_ppu(&[[79, 1, 0, 0]], &[0x2140]);
+    ppu.write_register(0x2001, 0b0001_1000);
+    render_lines(&mut ppu, 90);
+    assert_eq!(ppu.read_register(0x2002) & 0b0100_0000, 0);
+
+    let mut ppu = sprite_ppu(&[[79, 1, 0, 0]], &[0x2140]);
+    ppu.write_register(0x2001, 0b0001_1110);
+    render_lines(&mut ppu, 90);
+    assert_eq!(ppu.read_register(0x2002) & 0b0110_0000, 0b0100_0000);
+
+    // a sprite other than sprite 0 does not count
+    let mut ppu = sprite_ppu(&[[0xFF; 4], [79, 1, 0, 0]], &[0x2140]);
+    ppu.write_register(0x2001, 0b0001_1110);
+    render_lines(&mut ppu, 90);
+    assert_eq!(ppu.read_register(0x2002) & 0b0100_0000, 0);
+}
+
+fn read_palette(ppu: &mut PPU, addr: u16) -> u8 {
+    ppu.write_register(0x2006, (addr >> 8) as u8);
+    ppu.write_register(0x2006, (addr & 0xff) as u8);
+    // palette reads skip the PPUDATA buffer
+    ppu.read_register(0x2007)
+}
+
+#[test]
+fn attribute_palettes() {
+    let mut chr = vec![0; 0x2000];
+    chr[0x10..0x18].fill(0xFF);
+    let mut ppu = PPU::new();
+    ppu.load_rom(nrom(chr)).unwrap();
+
+    // tile 1 over the 32x32 pixel area at columns 4 - 7, rows 4 - 7, each 16x16
+    // quadrant of its attribute byte picking another palette
+    for row in 4..8 {
+        for col in 4..8 {
+            write_vram(&mut ppu, 0x2000 + row * 32 + col, 1);
+        }
+    }
+    write_vram(&mut ppu, 0x23C9, 0b11_10_01_00);
+    write_vram(&mut ppu, 0x3F00, BACKDROP);
+    write_vram(&mut ppu, 0x3F01, 0x21);
+    write_vram(&mut ppu, 0x3F05, 0x16);
+    write_vram(&mut ppu, 0x3F09, 0x2A);
+    write_vram(&mut ppu, 0x3F0D, 0x12);
+    // $3F10 is the backdrop
+    write_vram(&mut ppu, 0x3F10, 0x05);
+    ppu.write_register(0x2006, 0);
+    ppu.write_register(0x2006, 0);
+    ppu.write_register(0x2001, 0b0000_1010);
+    render_lines(&mut ppu, 70);
+
+    assert_eq!(pixel(&ppu, 32, 32), color(0x21));
+    assert_eq!(pixel(&ppu, 48, 32), color(0x16));
+    assert_eq!(pixel(&ppu, 32, 48), color(0x2A));
+    assert_eq!(pixel(&ppu, 63, 63), color(0x12));
+    assert_eq!(pixel(&ppu, 64, 32), color(0x05));
+    assert_eq!(pixel(&ppu, 31, 63), color(0x05));
+}
+
+#[test]
+fn palette_mirrors() {
+    let mut ppu = PPU::new();
+    ppu.load_rom(nrom(vec![0; 0x2000])).unwrap();
+
+    for (addr, data) in [
+        (0x3F10, 0x01),
+        (0x3F14, 0x02),
+        (0x3F18, 0x03),
+        (0x3F1C, 0x04),
+    ] {
+        write_vram(&mut ppu, addr, data);
+        assert_eq!(read_palette(&mut ppu, addr - 0x10), data);
+    }
+    // the other sprite entries have their own storage, $3F20-$3FFF mirror the table
+    write_vram(&mut ppu, 0x3F11, 0x30);
+    assert_eq!(read_palette(&mut ppu, 0x3F01), 0);
+    assert_eq!(read_palette(&mut ppu, 0x3F31), 0x30);
+    assert_eq!(read_palette(&mut ppu, 0x3FE0), 0x01);
+}
+
+#[test]
+fn backdrop_without_rendering() {
+    let mut ppu = PPU::new();
+    ppu.load_rom(nrom(vec![0; 0x2000])).unwrap();
+    write_vram(&mut ppu, 0x3F00, BACKDROP);
+    write_vram(&mut ppu, 0x3F03, 0x16);
+
+    // with rendering off and v inside the palette, that entry is the backdrop
+    ppu.write_register(0x2006, 0x3F);
+    ppu.write_register(0x2006, 0x03);
+    render_lines(&mut ppu, 10);
+    assert_eq!(pixel(&ppu, 0, 0), color(0x16));
+    assert_eq!(pixel(&ppu, 255, 9), color(0x16));
+
+    ppu.write_register(0x2006, 0x20);
+    ppu.write_register(0x2006, 0x00);
+    render_lines(&mut ppu, 20);
+    assert_eq!(pixel(&ppu, 0, 10), color(BACKDROP));
+    assert_eq!(pixel(&ppu, 255, 19), color(BACKDROP));
+}
+
+#[test]
+fn horizontal_mirroring() {
+    let data: Vec<u8> = std::fs::read("./tests/pacman.nes").unwrap();
+    let mut ppu = PPU::new();
+    ppu.load_rom(data).unwrap();
+
+    write_vram(&mut ppu, 0x2405, 0x42);
+    write_vram(&mut ppu, 0x2c05, 0x24);
+    assert_eq!(read_vram(&mut ppu, 0x2005), 0x42);
+    assert_eq!(read_vram(&mut ppu, 0x2805), 0x24);
+    // $3000-$3EFF mirrors $2000-$2EFF
+    assert_eq!(read_vram(&mut ppu, 0x3005), 0x42);
+}
+
+#[test]
+fn attribute_row_wrap() {
+    let mut chr = vec![0; 0x2000];
+    chr[0x0C00] = 0x11;
+    chr[0x0001] = 0x22;
+    let mut ppu = PPU::new();
+    ppu.load_rom(nrom(chr)).unwrap();
+
+    // coarse X 31 -> 0 switches the horizontal nametable, row 29 -> 0 with fine Y 7
+    // switches the vertical one: $73BF becomes $0C00
+    scroll_split(&mut ppu, 0, 0xF8, 0xEF);
+    ppu.write_register(0x2001, 0b0000_1000);
+    ppu.read_register(0x2007);
+    ppu.write_register(0x2001, 0);
+    ppu.read_register(0x2007);
+    assert_eq!(ppu.read_register(0x2007), 0x11);
+
+    // attribute row 31 wraps to 0 without switching nametable: $73E0 becomes $0001
+    scroll_split(&mut ppu, 0, 0x00, 0xFF);
+    ppu.write_register(0x2001, 0b0000_1000);
+    ppu.read_register(0x2007);
+    ppu.write_register(0x2001, 0);
+    ppu.read_register(0x2007);
+    assert_eq!(ppu.read_register(0x2007), 0x22);
+}
+
+fn run_to_vblank(ppu: &mut PPU) {
+    while !ppu.tick() {}
+}
+
+#[test]
+fn vblank_nmi() {
+    let data: Vec<u8> = std::fs::read("./tests/pacman.nes").unwrap();
+    let mut ppu = PPU::new();
+    ppu.load_rom(data).unwrap();
+
+    ppu.write_register(0x2000, 0x80);
+    run_to_vblank(&mut ppu);
+    assert!(ppu.poll_nmi());
+    assert!(!ppu.poll_nmi());
+
+    // enabling NMI while the vblank flag is still set fires at once
+    ppu.write_register(0x2000, 0x00);
+    ppu.write_register(0x2000, 0x80);
+    assert!(ppu.poll_nmi());
+
+    // reading $2002 clears the flag, so re-enabling does nothing
+    assert_eq!(ppu.read_register(0x2002) & 0x80, 0x80);
+    ppu.write_register(0x2000, 0x00);
+    ppu.write_register(0x2000, 0x80);
+    assert!(!ppu.poll_nmi());
+}
+
+#[test]
+fn vblank_race() {
+    // the flag is set by dot 1 of scanline 241: (dot of the $2002 read, vblank bit
+    // read, flag still set afterwards, NMI raised)
+    let cases = [
+        (0, false, true, true),
+        (1, false, false, false),
+        (2, true, false, false),
+        (3, true, false, false),
+        (4, true, false, true),
+    ];
+    for (dot, read, set_after, nmi) in cases {
+        let mut ppu = PPU::new();
+        ppu.load_rom(nrom(vec![0; 0x2000])).unwrap();
+        ppu.write_register(0x2000, 0x80);
+        while ppu.scanline() != 241 || ppu.dot() != dot {
+            ppu.tick();
+        }
+
+        assert_eq!(ppu.read_register(0x2002) & 0x80 != 0, read, "dot {}", dot);
+        for _ in 0..10 {
+            ppu.tick();
+        }
+        assert_eq!(ppu.poll_nmi(), nmi, "dot {}", dot);
+        assert_eq!(ppu.read_register(0x2002) & 0x80 != 0, set_after, "dot {}", dot);
+    }
+}
+
+#[test]
+fn chr_ram() {
+    // drop the CHR-ROM bank, the cartridge supplies 8KB of CHR-RAM instead
+    let mut data: Vec<u8> = std::fs::read("./tests/pacman.nes").unwrap();
+    data[5] = 0;
+    data.truncate(data.len() - 0x2000);
+    let mut ppu = PPU::new();
+    ppu.load_rom(data).unwrap();
+
+    write_vram(&mut ppu, 0x0010, 0x5A);
+    write_vram(&mut ppu, 0x1FFF, 0xA5);
+    assert_eq!(read_vram(&mut ppu, 0x0010), 0x5A);
+    assert_eq!(read_vram(&mut ppu, 0x1FFF), 0xA5);
+    // pattern table writes no longer leak into the nametables
+    assert_eq!(read_vram(&mut ppu, 0x2010), 0);
+}
@@ -0,0 +1,55 @@
+use std::{
+    cell::RefCell,
+    io::{self, Cursor, Seek, SeekFrom, Write},
+    rc::Rc,
+};
+
+use rust_nes::nes::Nes;
+
+// in-memory WAV file that stays readable after the recorder takes its writer
+#[derive(Clone, Default)]
+struct SharedCursor(Rc<RefCell<Cursor<Vec<u8>>>>);
+
+impl Write for SharedCursor {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+impl Seek for SharedCursor {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.borrow_mut().seek(pos)
+    }
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[test]
+fn record_frames() {
+    let data: Vec<u8> = std::fs::read("./tests/pacman.nes").unwrap();
+    let mut nes = Nes::new(data).unwrap();
+
+    let file = SharedCursor::default();
+    nes.start_recording(Box::new(file.clone()), 22050).unwrap();
+    for _ in 0..60 {
+        nes.run_frame();
+    }
+    nes.stop_recording().unwrap();
+
+    let wav = file.0.borrow().get_ref().clone();
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(u32_at(&wav, 4) as usize, wav.len() - 8);
+    assert_eq!(&wav[8..16], b"WAVEfmt ");
+    assert_eq!(u32_at(&wav, 24), 22050);
+    assert_eq!(&wav[36..40], b"data");
+    let data_bytes = u32_at(&wav, 40) as usize;
+    assert_eq!(data_bytes, wav.len() - 44);
+    // 60 NTSC frames of 16-bit mono are slightly less than a second
+    assert!((21900 * 2..22050 * 2).contains(&data_bytes));
+}